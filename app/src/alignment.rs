@@ -0,0 +1,446 @@
+//! Insertion/deletion-aware alignment of binary files.
+//!
+//! Files are aligned against a reference by finding unique blocks of the reference in the other
+//! file (anchors), extending those into maximal matching runs and keeping the longest chain of
+//! runs that is increasing in both files. Whatever lies between two runs is either paired up
+//! positionally or treated as inserted / deleted bytes.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Range,
+};
+
+const BLOCK_SIZE: usize = 32;
+const HASH_BASE: u64 = 0x100000001B3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct MatchedRun {
+    a: usize,
+    b: usize,
+    len: usize,
+}
+
+impl MatchedRun {
+    fn a_end(&self) -> usize {
+        self.a + self.len
+    }
+
+    fn b_end(&self) -> usize {
+        self.b + self.len
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlignOp {
+    /// `len` bytes of the reference at `a` line up with `len` bytes of the other file at `b`
+    Pair { a: usize, b: usize, len: usize },
+    /// `len` bytes of the reference at `a` are missing from the other file
+    Delete { a: usize, len: usize },
+    /// `len` bytes of the other file at `b` are not present in the reference, before reference offset `a`
+    Insert { a: usize, b: usize, len: usize },
+}
+
+fn block_hash(block: &[u8]) -> u64 {
+    block.iter().fold(0u64, |h, &byte| {
+        h.wrapping_mul(HASH_BASE).wrapping_add(byte as u64)
+    })
+}
+
+fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+fn common_suffix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count()
+}
+
+/// Finds maximal matching runs seeded by blocks that occur exactly once in `a`
+fn anchor_runs(a: &[u8], b: &[u8]) -> Vec<MatchedRun> {
+    if a.len() < BLOCK_SIZE || b.len() < BLOCK_SIZE {
+        return Vec::new();
+    }
+
+    // Blocks of the reference at block-aligned offsets. Repeated blocks are ambiguous and dropped.
+    let mut index: HashMap<u64, Option<usize>> = HashMap::new();
+    for i in (0..=a.len() - BLOCK_SIZE).step_by(BLOCK_SIZE) {
+        index
+            .entry(block_hash(&a[i..i + BLOCK_SIZE]))
+            .and_modify(|e| *e = None)
+            .or_insert(Some(i));
+    }
+
+    let top_power = (1..BLOCK_SIZE).fold(1u64, |p, _| p.wrapping_mul(HASH_BASE));
+
+    let mut runs: Vec<MatchedRun> = Vec::new();
+    let mut j = 0;
+    let mut hash = block_hash(&b[..BLOCK_SIZE]);
+
+    while j + BLOCK_SIZE <= b.len() {
+        if let Some(Some(i)) = index.get(&hash) {
+            let i = *i;
+            if a[i..i + BLOCK_SIZE] == b[j..j + BLOCK_SIZE] {
+                let b_floor = runs.last().map_or(0, |r| r.b_end());
+
+                let mut back = 0;
+                while i > back && j - back > b_floor && a[i - back - 1] == b[j - back - 1] {
+                    back += 1;
+                }
+
+                let mut fwd = BLOCK_SIZE;
+                while i + fwd < a.len() && j + fwd < b.len() && a[i + fwd] == b[j + fwd] {
+                    fwd += 1;
+                }
+
+                runs.push(MatchedRun {
+                    a: i - back,
+                    b: j - back,
+                    len: back + fwd,
+                });
+
+                j += fwd;
+                if j + BLOCK_SIZE <= b.len() {
+                    hash = block_hash(&b[j..j + BLOCK_SIZE]);
+                }
+                continue;
+            }
+        }
+
+        if j + BLOCK_SIZE < b.len() {
+            hash = hash
+                .wrapping_sub((b[j] as u64).wrapping_mul(top_power))
+                .wrapping_mul(HASH_BASE)
+                .wrapping_add(b[j + BLOCK_SIZE] as u64);
+        }
+        j += 1;
+    }
+
+    runs
+}
+
+/// Keeps the longest chain of runs (already ordered by `b`) that is also increasing in `a`,
+/// trimming runs so that consecutive ones don't overlap
+fn increasing_chain(runs: Vec<MatchedRun>) -> Vec<MatchedRun> {
+    // Patience-sorting LIS over the `a` offsets
+    let mut tails: Vec<usize> = Vec::new();
+    let mut prev: Vec<Option<usize>> = vec![None; runs.len()];
+
+    for (idx, run) in runs.iter().enumerate() {
+        let pos = tails.partition_point(|&t| runs[t].a < run.a);
+        if pos > 0 {
+            prev[idx] = Some(tails[pos - 1]);
+        }
+        if pos == tails.len() {
+            tails.push(idx);
+        } else {
+            tails[pos] = idx;
+        }
+    }
+
+    let mut chain = Vec::with_capacity(tails.len());
+    let mut cur = tails.last().copied();
+    while let Some(idx) = cur {
+        chain.push(runs[idx]);
+        cur = prev[idx];
+    }
+    chain.reverse();
+
+    let mut ret: Vec<MatchedRun> = Vec::with_capacity(chain.len());
+    for mut run in chain {
+        if let Some(last) = ret.last() {
+            let overlap = last
+                .a_end()
+                .saturating_sub(run.a)
+                .max(last.b_end().saturating_sub(run.b));
+            if overlap >= run.len {
+                continue;
+            }
+            run.a += overlap;
+            run.b += overlap;
+            run.len -= overlap;
+        }
+        ret.push(run);
+    }
+
+    ret
+}
+
+fn matching_runs(a: &[u8], b: &[u8]) -> Vec<MatchedRun> {
+    let prefix = common_prefix_len(a, b);
+    let suffix = common_suffix_len(&a[prefix..], &b[prefix..]);
+
+    let mut runs = Vec::new();
+    if prefix > 0 {
+        runs.push(MatchedRun {
+            a: 0,
+            b: 0,
+            len: prefix,
+        });
+    }
+
+    let a_mid = &a[prefix..a.len() - suffix];
+    let b_mid = &b[prefix..b.len() - suffix];
+    runs.extend(
+        increasing_chain(anchor_runs(a_mid, b_mid))
+            .into_iter()
+            .map(|r| MatchedRun {
+                a: r.a + prefix,
+                b: r.b + prefix,
+                len: r.len,
+            }),
+    );
+
+    if suffix > 0 {
+        runs.push(MatchedRun {
+            a: a.len() - suffix,
+            b: b.len() - suffix,
+            len: suffix,
+        });
+    }
+
+    runs
+}
+
+/// Pairs up the bytes between two matching runs positionally, the remainder being deleted or inserted
+fn push_unmatched(ops: &mut Vec<AlignOp>, a_range: Range<usize>, b_range: Range<usize>) {
+    let paired = a_range.len().min(b_range.len());
+
+    if paired > 0 {
+        ops.push(AlignOp::Pair {
+            a: a_range.start,
+            b: b_range.start,
+            len: paired,
+        });
+    }
+    if a_range.len() > paired {
+        ops.push(AlignOp::Delete {
+            a: a_range.start + paired,
+            len: a_range.len() - paired,
+        });
+    }
+    if b_range.len() > paired {
+        ops.push(AlignOp::Insert {
+            a: a_range.end,
+            b: b_range.start + paired,
+            len: b_range.len() - paired,
+        });
+    }
+}
+
+/// Aligns `b` against the reference `a`, returning operations that cover both files in order
+pub fn align(a: &[u8], b: &[u8]) -> Vec<AlignOp> {
+    let mut ops = Vec::new();
+    let (mut a_pos, mut b_pos) = (0, 0);
+
+    for run in matching_runs(a, b) {
+        push_unmatched(&mut ops, a_pos..run.a, b_pos..run.b);
+        ops.push(AlignOp::Pair {
+            a: run.a,
+            b: run.b,
+            len: run.len,
+        });
+        a_pos = run.a_end();
+        b_pos = run.b_end();
+    }
+    push_unmatched(&mut ops, a_pos..a.len(), b_pos..b.len());
+
+    ops
+}
+
+/// Lays out a reference and a number of files aligned against it (see [`align`]) in a shared
+/// coordinate space.
+///
+/// Returns, for the reference followed by each aligned file, the ranges of that space in which the
+/// file has no bytes (gaps), in increasing order.
+pub fn layout_gaps(reference_len: usize, alignments: &[Vec<AlignOp>]) -> Vec<Vec<Range<usize>>> {
+    // Width of the insertion slot before each reference offset: the longest insertion there
+    let mut slots: BTreeMap<usize, usize> = BTreeMap::new();
+    for op in alignments.iter().flatten() {
+        if let AlignOp::Insert { a, len, .. } = *op {
+            let width = slots.entry(a).or_default();
+            *width = (*width).max(len);
+        }
+    }
+
+    // (reference offset, slot width, slot start in the layout)
+    let mut slot_starts: Vec<(usize, usize, usize)> = Vec::with_capacity(slots.len());
+    let mut inserted_before = 0;
+    for (&a, &width) in slots.iter() {
+        slot_starts.push((a, width, a + inserted_before));
+        inserted_before += width;
+    }
+    let total_len = reference_len + inserted_before;
+
+    // Layout position of the reference byte at `a`
+    let ref_pos = |a: usize| -> usize {
+        let idx = slot_starts.partition_point(|&(s, _, _)| s <= a);
+        match idx {
+            0 => a,
+            _ => {
+                let (s, w, start) = slot_starts[idx - 1];
+                start + w + (a - s)
+            }
+        }
+    };
+    let slot_start = |a: usize| -> usize {
+        let idx = slot_starts.partition_point(|&(s, _, _)| s < a);
+        match slot_starts.get(idx) {
+            Some(&(s, _, start)) if s == a => start,
+            _ => ref_pos(a),
+        }
+    };
+
+    let gaps_from_occupied = |occupied: Vec<Range<usize>>| -> Vec<Range<usize>> {
+        let mut gaps = Vec::new();
+        let mut cursor = 0;
+        for r in occupied {
+            if r.start > cursor {
+                gaps.push(cursor..r.start);
+            }
+            cursor = cursor.max(r.end);
+        }
+        if cursor < total_len {
+            gaps.push(cursor..total_len);
+        }
+        gaps
+    };
+
+    // Reference bytes between two offsets, split around the insertion slots in between
+    let occupy_reference = |occupied: &mut Vec<Range<usize>>, a: usize, len: usize| {
+        let mut start = a;
+        for (&s, _) in slots.range(a + 1..a + len) {
+            occupied.push(ref_pos(start)..ref_pos(s - 1) + 1);
+            start = s;
+        }
+        occupied.push(ref_pos(start)..ref_pos(a + len - 1) + 1);
+    };
+
+    let mut ret = Vec::with_capacity(alignments.len() + 1);
+
+    let mut ref_occupied = Vec::new();
+    if reference_len > 0 {
+        occupy_reference(&mut ref_occupied, 0, reference_len);
+    }
+    ret.push(gaps_from_occupied(ref_occupied));
+
+    for ops in alignments.iter() {
+        let mut occupied = Vec::new();
+        for op in ops {
+            match *op {
+                AlignOp::Pair { a, len, .. } => occupy_reference(&mut occupied, a, len),
+                AlignOp::Insert { a, len, .. } => {
+                    let start = slot_start(a);
+                    occupied.push(start..start + len);
+                }
+                AlignOp::Delete { .. } => {}
+            }
+        }
+        ret.push(gaps_from_occupied(occupied));
+    }
+
+    ret
+}
+
+/// Largest number of byte comparisons to spend on finding a shift without anchors
+const MAX_BRUTE_FORCE_WORK: usize = 1 << 24;
+
+/// Files shorter than this may not contain a whole block at a block-aligned offset of the
+/// reference, so they're looked for in it as a whole instead
+const MAX_SEARCHED_LEN: usize = 2 * BLOCK_SIZE;
+
+/// Finds the shift `s` for which `b[j]` lines up with `a[j + s]` for the most bytes.
+/// Returns `None` if no shift makes any bytes match.
+pub fn best_shift(a: &[u8], b: &[u8]) -> Option<isize> {
+    // Vote for the shift of each anchored run, weighted by its length
+    let mut votes: HashMap<isize, usize> = HashMap::new();
+    for run in anchor_runs(a, b) {
+        *votes.entry(run.a as isize - run.b as isize).or_default() += run.len;
+    }
+
+    if let Some((&shift, _)) = votes
+        .iter()
+        .max_by_key(|&(&shift, &len)| (len, std::cmp::Reverse(shift.abs()), shift))
+    {
+        return Some(shift);
+    }
+
+    // Too short for anchors: look for all of `b` in the reference, the first occurrence being the
+    // smallest shift
+    if !b.is_empty() && b.len() < MAX_SEARCHED_LEN {
+        if let Some(pos) = a.windows(b.len()).position(|window| window == b) {
+            return Some(pos as isize);
+        }
+    }
+
+    // Without anchors, count the matching bytes of every shift when the inputs are small enough
+    if a.len().saturating_mul(b.len()) > MAX_BRUTE_FORCE_WORK {
+        return None;
+    }
+
+    (-(b.len() as isize) + 1..a.len() as isize)
+        .map(|shift| {
+            let matches = b
+                .iter()
+                .enumerate()
+                .filter(|&(j, &byte)| {
+                    let i = j as isize + shift;
+                    i >= 0 && a.get(i as usize) == Some(&byte)
+                })
+                .count();
+            (matches, std::cmp::Reverse(shift.abs()), shift)
+        })
+        .filter(|&(matches, _, _)| matches > 0)
+        .max()
+        .map(|(_, _, shift)| shift)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes that don't repeat in any block, so every block makes an anchor
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545F4914F6CDD1Du64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn best_shift_finds_file_starting_later_in_reference() {
+        let a = noise(0x1000);
+        let b = a[0x100..0x500].to_vec();
+
+        assert_eq!(best_shift(&a, &b), Some(0x100));
+    }
+
+    #[test]
+    fn best_shift_finds_file_starting_before_reference() {
+        let a = noise(0x1000);
+        let mut b = vec![0xAA; 0x50];
+        b.extend_from_slice(&a[..0x400]);
+
+        assert_eq!(best_shift(&a, &b), Some(-0x50));
+    }
+
+    #[test]
+    fn best_shift_finds_short_selection_in_large_reference() {
+        let a = noise(0x800000);
+        let b = a[0x4C4B43..0x4C4B53].to_vec();
+
+        assert_eq!(best_shift(&a, &b), Some(0x4C4B43));
+    }
+
+    #[test]
+    fn best_shift_without_matching_bytes() {
+        assert_eq!(best_shift(&[0x00; 0x1000], &[0xFF; 0x100]), None);
+    }
+}
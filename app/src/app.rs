@@ -1,20 +1,37 @@
-use crate::diff_state::DiffState;
+use crate::alignment::best_shift;
+use crate::diff_state::{
+    changed_ranges, DeviationBasis, DiffConfig, DiffInput, DiffMode, DiffState,
+};
+use crate::diff_worker::{DiffTask, DiffTaskStatus};
 use std::{
+    ops::Range,
     path::{Path, PathBuf},
     sync::atomic::Ordering,
 };
 
+use crate::compression::decompress;
+use crate::patch::{apply_patch, create_patch, positional_patch_ranges, PatchFormat};
+use crate::selection_ops::{parse_amount, parse_hex_bytes, SelectionOp};
 use crate::settings::ui::{byte_grouping_slider, show_settings_management_buttons};
+use crate::toasts::Toasts;
+use crate::tools::diff_stats::DiffStats;
+use crate::tools::hunk_navigator::{HunkAction, HunkNavigator};
+use crate::tools::minimap::Minimap;
+use crate::tools::patch_records::PatchRecords;
+use crate::unified_view::show_unified_view;
 use crate::{
-    bin_file::BinFile,
-    file_view::FileView,
+    bin_file::{read_file_bytes, BinFile, Endianness, FileData},
+    file_view::{FileView, VersionView},
     settings::{read_json_settings, show_theme_settings, write_json_settings, Settings},
-    workspace::{read_workspace_json, write_workspace_json, Workspace, WorkspaceFile},
+    workspace::{
+        read_workspace_json, write_workspace_json, IgnoreRegion, Workspace, WorkspaceFile,
+    },
 };
 use anyhow::Error;
 use bdiff_hex_view::cursor_state::CursorState;
 use bdiff_hex_view::selection::{HexViewSelection, HexViewSelectionSide, HexViewSelectionState};
-use eframe::egui::{Align, Layout, Modifiers, RichText, Ui};
+use bdiff_hex_view::{gap_bytes_before, view_to_file_offset, HexViewRow};
+use eframe::egui::{Align, KeyboardShortcut, Layout, Modifiers, RichText, Ui};
 use eframe::{
     egui::{self, Checkbox, Context, Style, ViewportCommand},
     epaint::{Rounding, Shadow},
@@ -32,6 +49,29 @@ struct OverwriteModal {
     open: bool,
 }
 
+/// What the operations on the selected bytes in the Edit menu are done with
+struct SelectionOpsInput {
+    pattern: String,
+    key: String,
+    int_width: usize,
+    amount: String,
+}
+
+impl Default for SelectionOpsInput {
+    fn default() -> Self {
+        Self {
+            pattern: String::new(),
+            key: String::new(),
+            int_width: 4,
+            amount: "1".to_owned(),
+        }
+    }
+}
+
+/// The positions at which files match the reference best, by file view id, or `None` for files
+/// that have nothing in common with it
+type AlignedPositions = Vec<(usize, Option<isize>)>;
+
 #[derive(Default)]
 pub struct BdiffApp {
     next_hv_id: usize,
@@ -48,6 +88,23 @@ pub struct BdiffApp {
     workspace: Workspace,
     started_with_arguments: bool,
     diff_state: DiffState,
+    diff_task: Option<DiffTask>,
+    /// Whether `diff_state` is out of date because the diff that would have updated it was
+    /// cancelled, so only a full recalculation can bring it up to date
+    diff_stale: bool,
+    /// Search for the positions at which files match the reference best
+    align_task: Option<DiffTask<AlignedPositions>>,
+    hunk_navigator: HunkNavigator,
+    diff_stats: DiffStats,
+    minimap: Minimap,
+    patch_records: PatchRecords,
+    toasts: Toasts,
+    /// Ids of the file views edited this frame and their contents before the edits, to update
+    /// the diffs with
+    edited: Vec<(usize, FileData)>,
+    /// Number of bytes to insert with the Edit menu
+    insert_len: usize,
+    selection_ops: SelectionOpsInput,
     bytes_per_row: usize,
     num_rows: usize,
 }
@@ -76,6 +133,7 @@ impl BdiffApp {
             file_views: hex_views,
             settings,
             started_with_arguments,
+            insert_len: 1,
             bytes_per_row: 0x10,
             num_rows: 25,
             ..Default::default()
@@ -92,6 +150,7 @@ impl BdiffApp {
 
             Workspace {
                 files: file_configs,
+                ..Default::default()
             }
         } else if config_path.exists() {
             read_workspace_json(config_path).unwrap()
@@ -106,6 +165,7 @@ impl BdiffApp {
                         fv.st.load_file(map);
                     }
                     fv.hv.set_style(hv_style.clone());
+                    fv.reference = file.reference;
                 }
                 Err(e) => {
                     log::error!("Failed to open file: {}", e);
@@ -115,6 +175,10 @@ impl BdiffApp {
 
         ret.workspace = config;
 
+        if ret.file_views.iter().any(|fv| fv.reference) {
+            ret.recalculate_diffs();
+        }
+
         ret
     }
 
@@ -131,10 +195,438 @@ impl BdiffApp {
         Ok(self.file_views.last_mut().unwrap())
     }
 
+    /// Applies the patch at `path` to the selected file, or to the reference if none is, and opens
+    /// the result as a file that only exists in memory, diffed against the original
+    fn open_patched_file(&mut self, path: &Path) -> Result<(), Error> {
+        let original = self
+            .last_selected_hv
+            .and_then(|id| self.file_views.iter().find(|fv| fv.id == id))
+            .or(self.reference_view())
+            .ok_or_else(|| Error::msg("No file to apply the patch to"))?;
+
+        let patch = read_file_bytes(path)?;
+        let source = original.file.data.read(0..original.file.data.len());
+        let applied = apply_patch(&patch, &source)?;
+        log::info!(
+            "Applied {} patch with {} records to {}",
+            applied.format,
+            applied.records.len(),
+            original.file.path.display()
+        );
+
+        let patch_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let file = BinFile::from_data(
+            format!(
+                "{} (patched with {})",
+                original.file.path.display(),
+                patch_name
+            )
+            .into(),
+            applied.data.into(),
+            original.file.endianness,
+        );
+        let original_id = original.id;
+        let original_pos = original.cur_pos;
+
+        let mut fv = FileView::new(file, self.next_hv_id, self.bytes_per_row, self.num_rows);
+        fv.cur_pos = original_pos;
+        fv.patch_records = Some(applied.records);
+        self.file_views.push(fv);
+        self.next_hv_id += 1;
+
+        self.patch_records.show = true;
+        // Diff against the original unless another file was picked to diff against
+        match self.file_views.iter().any(|fv| fv.reference) {
+            true => self.recalculate_diffs(),
+            false => self.set_reference(original_id, true),
+        }
+        Ok(())
+    }
+
+    /// Decompresses the data at the start of the selection in the selected file, or at the start
+    /// of the file if nothing is selected, and opens it as a file that only exists in memory
+    fn open_decompressed(&mut self) -> Result<(), Error> {
+        let fv = self
+            .last_selected_hv
+            .and_then(|id| self.file_views.iter().find(|fv| fv.id == id))
+            .or(self.file_views.first())
+            .ok_or_else(|| Error::msg("No file to decompress"))?;
+
+        let offset = match fv.hv.selection.state {
+            HexViewSelectionState::None => 0,
+            _ => fv
+                .hv
+                .selection
+                .start()
+                .checked_sub(fv.cur_pos)
+                .and_then(|view_pos| view_to_file_offset(view_pos, self.diff_state.gaps(fv.id)))
+                .filter(|&offset| offset < fv.file.data.len())
+                .ok_or_else(|| Error::msg("The selection doesn't start within the file"))?,
+        };
+
+        let (compression, data) = decompress(&fv.file.data, offset)?;
+        let summary = format!(
+            "Decompressed 0x{:X} bytes of {} data from 0x{:X}",
+            data.len(),
+            compression,
+            offset
+        );
+        log::info!("{} in {}", summary, fv.file.path.display());
+
+        let file = BinFile::from_data(
+            format!(
+                "{} ({} at 0x{:X})",
+                fv.file.path.display(),
+                compression,
+                offset
+            )
+            .into(),
+            data.into(),
+            fv.file.endianness,
+        );
+        let fv = FileView::new(file, self.next_hv_id, self.bytes_per_row, self.num_rows);
+        self.file_views.push(fv);
+        self.next_hv_id += 1;
+        self.toasts.add(summary);
+
+        self.recalculate_diffs();
+        Ok(())
+    }
+
     fn get_hex_view_by_id(&mut self, id: usize) -> Option<&mut FileView> {
         self.file_views.iter_mut().find(|fv| fv.id == id)
     }
 
+    /// The file view that was selected in last, if it's still open
+    fn selected_view(&self) -> Option<&FileView> {
+        self.last_selected_hv
+            .and_then(|id| self.file_views.iter().find(|fv| fv.id == id))
+    }
+
+    /// Whether typing goes into the selected file rather than being taken as shortcuts
+    fn is_typing_into_file(&self) -> bool {
+        self.selected_view().is_some_and(|fv| {
+            fv.editable
+                && fv.version_view.is_none()
+                && fv.hv.selection.state != HexViewSelectionState::None
+        })
+    }
+
+    /// Makes an edit to file view `id` with `edit`, which returns whether it changed anything, so
+    /// the diffs are updated with it
+    fn edit_file(&mut self, id: usize, edit: impl FnOnce(&mut FileView) -> bool) -> bool {
+        let Some(fv) = self.get_hex_view_by_id(id) else {
+            return false;
+        };
+
+        let old_data = fv.file.data.clone();
+        let changed = edit(fv);
+        if changed {
+            let resizes = fv.file.edits.take_resizes();
+            fv.shift_offsets(&resizes);
+            self.edited.push((id, old_data));
+        }
+        changed
+    }
+
+    /// Copies the bytes file view `from` shows at the sorted global positions in `positions` over
+    /// those file view `to` shows there, as a single step that can be undone. Bytes only one of
+    /// them shows, where the other has a gap, are inserted or deleted along with the rest.
+    fn copy_between_files(&mut self, from: usize, to: usize, positions: &[Range<usize>]) {
+        let Some(source) = self.file_views.iter().find(|fv| fv.id == from) else {
+            return;
+        };
+        let source_gaps = self.diff_state.gaps(from);
+        let copies: Vec<(Range<usize>, Vec<u8>)> = positions
+            .iter()
+            .map(|p| {
+                let range = source.file_range(p, source_gaps);
+                (p.clone(), source.file.data.read(range).into_owned())
+            })
+            .collect();
+        let source_name = source.file_name();
+
+        let gaps = self.diff_state.gaps(to).map(<[_]>::to_vec);
+        let mut target_name = String::new();
+        let changed = self.edit_file(to, |fv| {
+            target_name = fv.file_name();
+            // Later ranges go first, so inserting or deleting bytes doesn't move earlier ones
+            let replacements: Vec<(Range<usize>, &[u8])> = copies
+                .iter()
+                .rev()
+                .map(|(p, bytes)| (fv.file_range(p, gaps.as_deref()), &bytes[..]))
+                .collect();
+            fv.file.replace(&replacements)
+        });
+
+        match changed {
+            true => log::info!("Copied bytes from {} to {}", source_name, target_name),
+            false => self.toasts.add(format!(
+                "{} already has the bytes of {} there",
+                target_name, source_name
+            )),
+        }
+    }
+
+    /// Global positions of the bytes selected in file view `id`
+    fn selected_positions(&self, id: usize) -> Option<Range<usize>> {
+        let fv = self.file_views.iter().find(|fv| fv.id == id)?;
+        match fv.hv.selection.state {
+            HexViewSelectionState::None => None,
+            _ => Some(fv.hv.selection.start()..fv.hv.selection.end() + 1),
+        }
+    }
+
+    /// Shows the actions that copy the bytes selected in file view `id` between it and the others
+    fn show_merge_menu(&mut self, ui: &mut Ui, id: usize) {
+        let Some(selected) = self.selected_positions(id) else {
+            return;
+        };
+        let hunks: Vec<Range<usize>> = self
+            .diff_state
+            .hunks()
+            .iter()
+            .filter(|h| h.start < selected.end && h.end > selected.start)
+            .map(|h| h.start.max(selected.start)..h.end.min(selected.end))
+            .collect();
+        // Earlier versions of a file can be copied from, but not edited
+        let targets: Vec<(usize, String)> = self
+            .file_views
+            .iter()
+            .filter(|fv| fv.id != id && fv.version_view.is_none())
+            .map(|fv| (fv.id, fv.file_name()))
+            .collect();
+
+        ui.menu_button("Copy selection to", |ui| {
+            for (to, name) in targets.iter() {
+                if ui.button(name).clicked() {
+                    self.copy_between_files(id, *to, std::slice::from_ref(&selected));
+                    ui.close_menu();
+                }
+            }
+        });
+        ui.add_enabled_ui(!hunks.is_empty(), |ui| {
+            ui.menu_button("Copy hunks in selection to", |ui| {
+                for (to, name) in targets.iter() {
+                    if ui.button(name).clicked() {
+                        self.copy_between_files(id, *to, &hunks);
+                        ui.close_menu();
+                    }
+                }
+            });
+        });
+
+        let reference_id = self.diff_state.reference_id();
+        let can_revert = !hunks.is_empty()
+            && reference_id.is_some_and(|r| r != id)
+            && self
+                .selected_view()
+                .is_some_and(|fv| fv.version_view.is_none());
+        if ui
+            .add_enabled(can_revert, egui::Button::new("Revert hunks in selection"))
+            .on_hover_text("Replace the differing bytes with those of the reference file")
+            .clicked()
+        {
+            if let Some(reference_id) = reference_id {
+                self.copy_between_files(reference_id, id, &hunks);
+            }
+            ui.close_menu();
+        }
+    }
+
+    /// Shows the operations that rewrite the bytes selected in file view `id`
+    fn show_selection_ops_menu(&mut self, ui: &mut Ui, id: usize, gaps: Option<&[Range<usize>]>) {
+        let endianness = self
+            .get_hex_view_by_id(id)
+            .map_or(Endianness::Little, |fv| fv.file.endianness);
+        let input = &mut self.selection_ops;
+        let mut op = None;
+
+        if ui.button("Zero out").clicked() {
+            op = Some(Ok(SelectionOp::Fill(vec![0])));
+        }
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut input.pattern)
+                    .hint_text("Hex bytes")
+                    .desired_width(120.0),
+            );
+            if ui.button("Fill with pattern").clicked() {
+                op = Some(parse_hex_bytes(&input.pattern).map(SelectionOp::Fill));
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut input.key)
+                    .hint_text("Hex bytes")
+                    .desired_width(120.0),
+            );
+            if ui.button("XOR with key").clicked() {
+                op = Some(parse_hex_bytes(&input.key).map(SelectionOp::Xor));
+            }
+        });
+
+        ui.separator();
+        let endianness_name = match endianness {
+            Endianness::Little => "little",
+            Endianness::Big => "big",
+        };
+        ui.label(format!("As {}-endian integers:", endianness_name));
+        ui.horizontal(|ui| {
+            for width in [1, 2, 4, 8] {
+                ui.radio_value(&mut input.int_width, width, format!("{}-bit", width * 8));
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut input.amount)
+                    .hint_text("Amount")
+                    .desired_width(80.0),
+            );
+            if ui.button("Increment").clicked() {
+                op = Some(parse_amount(&input.amount).map(|amount| SelectionOp::Add {
+                    width: input.int_width,
+                    amount,
+                }));
+            }
+            if ui.button("Decrement").clicked() {
+                op = Some(parse_amount(&input.amount).map(|amount| SelectionOp::Add {
+                    width: input.int_width,
+                    amount: amount.wrapping_neg(),
+                }));
+            }
+        });
+
+        ui.separator();
+        for width in [2, 4, 8] {
+            if ui
+                .button(format!("Byte-swap {}-byte units", width))
+                .clicked()
+            {
+                op = Some(Ok(SelectionOp::ByteSwap(width)));
+            }
+        }
+        if ui.button("Reverse").clicked() {
+            op = Some(Ok(SelectionOp::Reverse));
+        }
+
+        let Some(op) = op else {
+            return;
+        };
+        let result = op.and_then(|op| {
+            let mut result = Ok(());
+            self.edit_file(id, |fv| {
+                fv.apply_to_selection(&op, gaps).unwrap_or_else(|e| {
+                    result = Err(e);
+                    false
+                })
+            });
+            result
+        });
+        match result {
+            Ok(()) => ui.close_menu(),
+            Err(e) => self.toasts.add(format!("Failed to change bytes: {}", e)),
+        }
+    }
+
+    fn undo_edit(&mut self, id: usize) {
+        self.edit_file(id, |fv| fv.file.undo());
+    }
+
+    fn redo_edit(&mut self, id: usize) {
+        self.edit_file(id, |fv| fv.file.redo());
+    }
+
+    /// Saves file view `id`, asking where to if `save_as` is set or the file only exists in memory
+    fn save_file(&mut self, id: usize, save_as: bool) {
+        let Some(fv) = self.get_hex_view_by_id(id) else {
+            return;
+        };
+
+        let old_path = fv.file.path.clone();
+        let result = match save_as || fv.file.is_in_memory() {
+            true => {
+                let file_name = old_path.file_name().unwrap_or_default().to_string_lossy();
+                let Some(path) = rfd::FileDialog::new().set_file_name(file_name).save_file() else {
+                    return;
+                };
+                fv.file.save_as(&path)
+            }
+            false => fv.file.save(),
+        };
+
+        match result {
+            Ok(()) => {
+                let path = fv.file.path.clone();
+                let reference = fv.reference;
+                log::info!("Saved {}", path.display());
+                self.toasts.add(format!(
+                    "Saved {}",
+                    path.file_name().unwrap_or_default().to_string_lossy()
+                ));
+
+                if path != old_path {
+                    match self.workspace.files.iter_mut().find(|f| f.path == old_path) {
+                        Some(file) => file.path = path,
+                        None => self.workspace.files.push(WorkspaceFile {
+                            reference,
+                            ..path.into()
+                        }),
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("Failed to save {}: {}", old_path.display(), e);
+                self.toasts.add(format!("Failed to save: {}", e));
+            }
+        }
+    }
+
+    /// Writes what was typed into the selected file, and undoes, redoes and saves on shortcuts
+    fn handle_edit_input(&mut self, ctx: &Context) {
+        let Some(id) = self.selected_view().map(|fv| fv.id) else {
+            return;
+        };
+        if ctx.wants_keyboard_input() {
+            return;
+        }
+
+        let shortcut = |modifiers: Modifiers, key: egui::Key| {
+            ctx.input_mut(|i| i.consume_shortcut(&KeyboardShortcut::new(modifiers, key)))
+        };
+        if shortcut(Modifiers::COMMAND, egui::Key::S)
+            && self.selected_view().is_some_and(|fv| fv.can_save())
+        {
+            self.save_file(id, false);
+        }
+        // Ctrl+Z also matches Ctrl+Shift+Z, so that has to be checked first
+        if shortcut(Modifiers::COMMAND | Modifiers::SHIFT, egui::Key::Z)
+            || shortcut(Modifiers::COMMAND, egui::Key::Y)
+        {
+            self.redo_edit(id);
+        } else if shortcut(Modifiers::COMMAND, egui::Key::Z) {
+            self.undo_edit(id);
+        }
+
+        let gaps = self.diff_state.gaps(id).map(<[_]>::to_vec);
+        if self.is_typing_into_file() {
+            if ctx.input_mut(|i| i.consume_key(Modifiers::NONE, egui::Key::Insert)) {
+                if let Some(fv) = self.get_hex_view_by_id(id) {
+                    fv.insert_mode = !fv.insert_mode;
+                }
+            }
+            if ctx.input_mut(|i| i.consume_key(Modifiers::NONE, egui::Key::Delete)) {
+                self.edit_file(id, |fv| fv.delete_selection(gaps.as_deref()));
+            }
+        }
+        self.edit_file(id, |fv| fv.type_into_selection(ctx, gaps.as_deref()));
+        if let Some(fv) = self.selected_view() {
+            if fv.hv.selection != self.global_selection {
+                self.global_selection = fv.hv.selection.clone();
+            }
+        }
+    }
+
     fn move_selection(&mut self, ctx: &Context) {
         let bytes_per_row = self.bytes_per_row;
 
@@ -149,8 +641,8 @@ impl BdiffApp {
                     changed = true;
                 }
                 if ctx.input(|i| i.key_pressed(egui::Key::ArrowRight))
-                    && fv.hv.selection.start() < fv.file.data.len() - 1
-                    && fv.hv.selection.end() < fv.file.data.len() - 1
+                    && fv.hv.selection.start() < fv.file.data.len().saturating_sub(1)
+                    && fv.hv.selection.end() < fv.file.data.len().saturating_sub(1)
                 {
                     fv.hv.selection.adjust_cur_pos(1);
                     changed = true;
@@ -163,8 +655,8 @@ impl BdiffApp {
                     changed = true;
                 }
                 if ctx.input(|i| i.key_pressed(egui::Key::ArrowDown))
-                    && fv.hv.selection.start() < fv.file.data.len() - bytes_per_row
-                    && fv.hv.selection.end() < fv.file.data.len() - bytes_per_row
+                    && fv.hv.selection.start() < fv.file.data.len().saturating_sub(bytes_per_row)
+                    && fv.hv.selection.end() < fv.file.data.len().saturating_sub(bytes_per_row)
                 {
                     fv.hv.selection.adjust_cur_pos(bytes_per_row as isize);
                     changed = true;
@@ -234,21 +726,88 @@ impl BdiffApp {
         self.global_view_pos = 0.max(self.global_view_pos as isize + delta) as usize;
     }
 
+    fn is_collapsing(&self) -> bool {
+        self.is_diffing() && self.settings.collapse_identical
+    }
+
+    /// Rows of the file views when identical regions are collapsed
+    fn view_rows(&self) -> Option<Vec<HexViewRow>> {
+        match self.is_collapsing() {
+            true => Some(self.diff_state.collapsed_rows(
+                self.global_view_pos,
+                self.get_furthest_file_pos(),
+                self.bytes_per_row,
+                self.num_rows,
+                self.settings.context_rows,
+            )),
+            false => None,
+        }
+    }
+
+    /// Moves the view by `delta` bytes, stepping over collapsed regions as if they were one row
+    fn scroll_global_pos(&mut self, delta: isize) {
+        if !self.is_collapsing() {
+            self.move_global_pos(delta);
+            return;
+        }
+
+        let bytes_per_row = self.bytes_per_row;
+        let context_rows = self.settings.context_rows;
+        let end = self.get_furthest_file_pos();
+        let mut pos = self.global_view_pos;
+
+        for _ in 0..delta.unsigned_abs().div_ceil(bytes_per_row) {
+            match delta > 0 {
+                true => {
+                    pos = match &self.diff_state.collapsed_rows(
+                        pos,
+                        end,
+                        bytes_per_row,
+                        1,
+                        context_rows,
+                    )[0]
+                    {
+                        HexViewRow::Folded(fold) => fold.end.max(pos + bytes_per_row),
+                        HexViewRow::Bytes(row_start) => row_start + bytes_per_row,
+                    }
+                }
+                false => {
+                    let Some(above) = pos.checked_sub(bytes_per_row) else {
+                        break;
+                    };
+                    pos = self
+                        .diff_state
+                        .fold_start(above, bytes_per_row, context_rows)
+                        .unwrap_or(above);
+                }
+            }
+        }
+
+        self.set_global_pos(pos);
+    }
+
+    /// Moves the view so the row containing `pos` is at the top
+    fn jump_to(&mut self, pos: usize) {
+        self.set_global_pos(pos - (pos % self.bytes_per_row));
+    }
+
     fn move_global_pos_enter(&mut self, furthest_file_pos: usize, bytes_per_screen: usize) {
         if self.is_diffing() {
-            let last_byte = self.global_view_pos + bytes_per_screen;
-
-            if last_byte < furthest_file_pos {
-                match self.diff_state.get_next_diff(last_byte) {
-                    Some(next_diff) => {
-                        // Move to the next diff
-                        let new_pos = next_diff - (next_diff % self.bytes_per_row);
-                        self.set_global_pos(new_pos);
-                    }
-                    None => {
-                        // Move to the end of the diff
-                        self.set_global_pos(0.max(furthest_file_pos - bytes_per_screen))
-                    }
+            // Anything starting past the top row is considered the next diff
+            let top_row_end = self.global_view_pos + self.bytes_per_row;
+
+            match self.diff_state.next_hunk(top_row_end) {
+                Some(hunk) => {
+                    // Move to the next diff
+                    self.jump_to(hunk.start);
+                }
+                None => {
+                    // Move to the end of the diff
+                    self.set_global_pos(
+                        furthest_file_pos
+                            .saturating_sub(bytes_per_screen)
+                            .max(self.global_view_pos),
+                    )
                 }
             }
         } else {
@@ -257,10 +816,33 @@ impl BdiffApp {
         }
     }
 
+    fn move_to_prev_diff(&mut self) {
+        if let Some(hunk) = self.diff_state.prev_hunk(self.global_view_pos) {
+            self.jump_to(hunk.start);
+        }
+    }
+
+    fn move_to_first_diff(&mut self) {
+        if let Some(hunk) = self.diff_state.hunks().first() {
+            self.jump_to(hunk.start);
+        }
+    }
+
+    fn move_to_last_diff(&mut self) {
+        if let Some(hunk) = self.diff_state.hunks().last() {
+            self.jump_to(hunk.start);
+        }
+    }
+
     fn is_diffing(&self) -> bool {
         self.file_views.len() > 1 && self.settings.diff_enabled
     }
 
+    /// Whether the hunks match the files as they are now
+    fn diff_is_current(&self) -> bool {
+        self.diff_task.is_none() && !self.diff_stale
+    }
+
     fn scroll_view(&mut self, ctx: &Context) {
         let scroll_y = ctx.input(|i| i.raw_scroll_delta.y);
 
@@ -282,14 +864,14 @@ impl BdiffApp {
                     self.scroll_overflow -= (scroll_amt * scroll_threshold) as f32;
                 }
             }
-            self.move_global_pos(-scroll_amt * lines_per_scroll * self.bytes_per_row as isize);
+            self.scroll_global_pos(-scroll_amt * lines_per_scroll * self.bytes_per_row as isize);
         }
     }
 
     fn get_furthest_file_pos(&self) -> usize {
         self.file_views
             .iter()
-            .map(|fv| fv.cur_pos + fv.file.data.len())
+            .map(|fv| fv.cur_pos + self.diff_state.display_len(fv))
             .max()
             .unwrap()
     }
@@ -303,13 +885,13 @@ impl BdiffApp {
             self.set_global_pos(0);
         }
         if ctx.input(|i| i.key_pressed(egui::Key::End)) {
-            self.set_global_pos(0.max(furthest_file_pos - bytes_per_screen))
+            self.set_global_pos(furthest_file_pos.saturating_sub(bytes_per_screen))
         }
         if ctx.input(|i| i.key_pressed(egui::Key::PageUp)) {
-            self.move_global_pos(-(bytes_per_screen as isize))
+            self.scroll_global_pos(-(bytes_per_screen as isize))
         }
         if ctx.input(|i| i.key_pressed(egui::Key::PageDown)) {
-            self.move_global_pos(bytes_per_screen as isize)
+            self.scroll_global_pos(bytes_per_screen as isize)
         }
         if ctx.input(|i| i.key_pressed(egui::Key::ArrowLeft)) {
             self.move_global_pos(-1);
@@ -318,10 +900,10 @@ impl BdiffApp {
             self.move_global_pos(1);
         }
         if ctx.input(|i| i.key_pressed(egui::Key::ArrowUp)) {
-            self.move_global_pos(-(self.bytes_per_row as isize));
+            self.scroll_global_pos(-(self.bytes_per_row as isize));
         }
         if ctx.input(|i| i.key_pressed(egui::Key::ArrowDown)) {
-            self.move_global_pos(self.bytes_per_row as isize);
+            self.scroll_global_pos(self.bytes_per_row as isize);
         }
         if ctx.input(|i| i.key_pressed(egui::Key::Enter)) {
             self.move_global_pos_enter(furthest_file_pos, bytes_per_screen);
@@ -336,6 +918,10 @@ impl BdiffApp {
         if ctx.input(|i| i.modifiers.shift) {
             // Move selection
             self.move_selection(ctx);
+
+            if self.is_diffing() && ctx.input(|i| i.key_pressed(egui::Key::Enter)) {
+                self.move_to_prev_diff();
+            }
         } else {
             if self.file_views.iter().any(|fv| fv.pos_locked) {
                 self.nudge_files(ctx);
@@ -353,7 +939,11 @@ impl BdiffApp {
 
         for fv in self.file_views.iter() {
             if self.last_selected_hv.is_some() && fv.id == self.last_selected_hv.unwrap() {
-                let selected_bytes = fv.hv.get_selected_bytes(&fv.file.data, fv.cur_pos);
+                let selected_bytes = fv
+                    .hv
+                    .selected_range(fv.file.data.len(), fv.cur_pos, self.diff_state.gaps(fv.id))
+                    .map(|range| fv.file.data.read(range))
+                    .unwrap_or_default();
 
                 let selected_bytes: String = match fv.hv.selection.side {
                     HexViewSelectionSide::Hex => selected_bytes
@@ -362,7 +952,7 @@ impl BdiffApp {
                         .collect::<Vec<String>>()
                         .join(" "),
                     HexViewSelectionSide::Ascii => {
-                        String::from_utf8_lossy(selected_bytes).to_string()
+                        String::from_utf8_lossy(&selected_bytes).to_string()
                     }
                 };
                 // convert selected_bytes to an ascii string
@@ -449,7 +1039,7 @@ impl eframe::App for BdiffApp {
             overwrite_modal.open();
         }
 
-        if ctx.input(|i| i.key_pressed(egui::Key::G)) {
+        if !self.is_typing_into_file() && ctx.input(|i| i.key_pressed(egui::Key::G)) {
             if goto_modal.is_open() {
                 goto_modal.close();
             } else {
@@ -473,6 +1063,7 @@ impl eframe::App for BdiffApp {
 
         // Standard HexView input
         if !(overwrite_modal.is_open() || goto_modal.is_open()) {
+            self.handle_edit_input(ctx);
             self.handle_hex_view_input(ctx);
         }
 
@@ -494,6 +1085,46 @@ impl eframe::App for BdiffApp {
 
                         ui.close_menu();
                     }
+                    if ui
+                        .add_enabled(!self.file_views.is_empty(), egui::Button::new("Apply patch..."))
+                        .on_hover_text("Open the selected file with an IPS, BPS or UPS patch applied")
+                        .clicked()
+                    {
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("Patch", &["ips", "bps", "ups"])
+                            .pick_file()
+                        {
+                            if let Err(e) = self.open_patched_file(&path) {
+                                log::error!("Failed to apply patch: {}", e);
+                                self.toasts.add(format!("Failed to apply patch: {}", e));
+                            }
+                        }
+
+                        ui.close_menu();
+                    }
+                    let selected = self.selected_view().map(|fv| (fv.id, fv.can_save()));
+                    if ui
+                        .add_enabled(
+                            selected.is_some_and(|(_, can_save)| can_save),
+                            egui::Button::new("Save file (Ctrl+S)"),
+                        )
+                        .on_hover_text("Write the edits made to the selected file to it")
+                        .clicked()
+                    {
+                        if let Some((id, _)) = selected {
+                            self.save_file(id, false);
+                        }
+                        ui.close_menu();
+                    }
+                    if ui
+                        .add_enabled(selected.is_some(), egui::Button::new("Save file as..."))
+                        .clicked()
+                    {
+                        if let Some((id, _)) = selected {
+                            self.save_file(id, true);
+                        }
+                        ui.close_menu();
+                    }
                     if ui.button("Save Workspace").clicked() {
                         if self.started_with_arguments {
                             self.overwrite_modal.open = true;
@@ -508,6 +1139,80 @@ impl eframe::App for BdiffApp {
                     }
                 });
 
+                ui.menu_button("Edit", |ui| {
+                    let Some(id) = self.last_selected_hv else {
+                        ui.label("Select bytes in a file to edit it");
+                        return;
+                    };
+
+                    if let Some(fv) = self.get_hex_view_by_id(id) {
+                        ui.add_enabled_ui(fv.version_view.is_none(), |ui| {
+                            ui.checkbox(&mut fv.editable, "Type over the selection");
+                            ui.add_enabled(
+                                fv.editable,
+                                Checkbox::new(
+                                    &mut fv.insert_mode,
+                                    "Insert instead of overwriting (Ins)",
+                                ),
+                            );
+                        });
+                    }
+                    let (can_undo, can_redo) = self.selected_view().map_or((false, false), |fv| {
+                        (fv.file.edits.can_undo(), fv.file.edits.can_redo())
+                    });
+                    if ui
+                        .add_enabled(can_undo, egui::Button::new("Undo (Ctrl+Z)"))
+                        .clicked()
+                    {
+                        self.undo_edit(id);
+                        ui.close_menu();
+                    }
+                    if ui
+                        .add_enabled(can_redo, egui::Button::new("Redo (Ctrl+Y)"))
+                        .clicked()
+                    {
+                        self.redo_edit(id);
+                        ui.close_menu();
+                    }
+
+                    ui.separator();
+                    let gaps = self.diff_state.gaps(id).map(<[_]>::to_vec);
+                    let has_selection = self.selected_view().is_some_and(|fv| {
+                        fv.version_view.is_none()
+                            && fv.hv.selection.state != HexViewSelectionState::None
+                    });
+                    ui.add_enabled_ui(has_selection, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::DragValue::new(&mut self.insert_len)
+                                    .range(1..=0x100000)
+                                    .prefix("0x")
+                                    .hexadecimal(1, false, true),
+                            );
+                            if ui.button("Insert zero bytes before selection").clicked() {
+                                let bytes = vec![0; self.insert_len];
+                                self.edit_file(id, |fv| {
+                                    fv.insert_before_selection(&bytes, gaps.as_deref())
+                                });
+                                ui.close_menu();
+                            }
+                        });
+                        if ui.button("Delete selected bytes (Del)").clicked() {
+                            self.edit_file(id, |fv| fv.delete_selection(gaps.as_deref()));
+                            ui.close_menu();
+                        }
+                        ui.menu_button("Change selected bytes", |ui| {
+                            self.show_selection_ops_menu(ui, id, gaps.as_deref());
+                        });
+
+                        // Positions only line up once the diff is done
+                        ui.separator();
+                        ui.add_enabled_ui(self.is_diffing() && self.diff_is_current(), |ui| {
+                            self.show_merge_menu(ui, id);
+                        });
+                    });
+                });
+
                 ui.menu_button("Action", |ui| {
                     if ui.button("Go to address (G)").clicked() {
                         self.goto_modal.value = "0x".to_owned();
@@ -527,6 +1232,94 @@ impl eframe::App for BdiffApp {
                         );
                     }
 
+                    ui.add_enabled_ui(self.is_diffing(), |ui| {
+                        if ui.button("Jump to previous diff (Shift+Enter)").clicked() {
+                            self.move_to_prev_diff();
+                        }
+                        if ui.button("Jump to first diff").clicked() {
+                            self.move_to_first_diff();
+                        }
+                        if ui.button("Jump to last diff").clicked() {
+                            self.move_to_last_diff();
+                        }
+                    });
+
+                    ui.add_enabled_ui(self.file_views.len() > 1, |ui| {
+                        if ui
+                            .button("Auto-align with reference")
+                            .on_hover_text("Move the selected file to where it matches the reference best")
+                            .clicked()
+                        {
+                            self.auto_align(false);
+                            ui.close_menu();
+                        }
+                        if ui
+                            .add_enabled(
+                                self.global_selection.state != HexViewSelectionState::None,
+                                egui::Button::new("Auto-align selection with reference"),
+                            )
+                            .on_hover_text("Move the selected file to where its selected bytes match the reference best")
+                            .clicked()
+                        {
+                            self.auto_align(true);
+                            ui.close_menu();
+                        }
+                    });
+
+                    ui.add_enabled_ui(self.is_diffing(), |ui| {
+                        if ui
+                            .add_enabled(
+                                self.global_selection.state != HexViewSelectionState::None,
+                                egui::Button::new("Ignore selection in diffs"),
+                            )
+                            .clicked()
+                        {
+                            self.ignore_selection();
+                            ui.close_menu();
+                        }
+                        if ui
+                            .add_enabled(
+                                !self.workspace.ignore.is_empty(),
+                                egui::Button::new("Clear ignored regions"),
+                            )
+                            .clicked()
+                        {
+                            self.workspace.ignore.clear();
+                            self.recalculate_diffs();
+                            ui.close_menu();
+                        }
+                    });
+
+                    if ui
+                        .add_enabled(
+                            !self.file_views.is_empty(),
+                            egui::Button::new("Open decompressed data"),
+                        )
+                        .on_hover_text("Open the Yaz0, Yay0/MIO0, LZ77 or zlib data at the start of the selection, or of the selected file, as a new file")
+                        .clicked()
+                    {
+                        if let Err(e) = self.open_decompressed() {
+                            log::error!("Failed to decompress: {}", e);
+                            self.toasts.add(format!("Failed to decompress: {}", e));
+                        }
+                        ui.close_menu();
+                    }
+
+                    ui.add_enabled_ui(self.file_views.len() > 1, |ui| {
+                        ui.menu_button("Export patch from reference", |ui| {
+                            for format in [PatchFormat::Ips, PatchFormat::Bps, PatchFormat::Ups] {
+                                if ui
+                                    .button(format!("{}...", format))
+                                    .on_hover_text("Write a patch that turns the reference into the selected file")
+                                    .clicked()
+                                {
+                                    self.export_patch(format);
+                                    ui.close_menu();
+                                }
+                            }
+                        });
+                    });
+
                     if ui.button("Copy selected bytes/hex").clicked() {
                         self.copy_selected_bytes(ctx);
                     }
@@ -547,11 +1340,53 @@ impl eframe::App for BdiffApp {
                     if ui
                         .add_enabled(self.file_views.len() > 1, diff_checkbox)
                         .clicked()
-                        && self.settings.diff_enabled
                     {
                         self.recalculate_diffs()
                     }
 
+                    ui.add_enabled_ui(self.settings.diff_enabled, |ui| {
+                        let prev_diff_mode = self.settings.diff_mode;
+                        for mode in [DiffMode::Positional, DiffMode::Aligned, DiffMode::Mips] {
+                            ui.radio_value(&mut self.settings.diff_mode, mode, mode.to_string());
+                        }
+                        if self.settings.diff_mode != prev_diff_mode {
+                            self.recalculate_diffs();
+                        }
+
+                        ui.checkbox(
+                            &mut self.settings.per_file_diff_colors,
+                            "Color differences per file",
+                        );
+                        ui.add_enabled_ui(self.settings.per_file_diff_colors, |ui| {
+                            ui.label("Compare each file against");
+                            let prev_basis = self.settings.deviation_basis;
+                            for basis in [DeviationBasis::Majority, DeviationBasis::Reference] {
+                                ui.radio_value(
+                                    &mut self.settings.deviation_basis,
+                                    basis,
+                                    basis.to_string(),
+                                );
+                            }
+                            if self.settings.deviation_basis != prev_basis {
+                                self.recalculate_diffs();
+                            }
+                        });
+
+                        ui.checkbox(
+                            &mut self.settings.collapse_identical,
+                            "Collapse identical rows",
+                        );
+                        ui.add_enabled_ui(self.settings.collapse_identical, |ui| {
+                            ui.horizontal(|ui| {
+                                ui.add(
+                                    egui::DragValue::new(&mut self.settings.context_rows)
+                                        .range(0..=32),
+                                );
+                                ui.label("Context rows");
+                            });
+                        });
+                    });
+
                     ui.separator();
                     ui.label("Interface");
                     byte_grouping_slider(ui, &mut self.settings.byte_grouping);
@@ -559,6 +1394,31 @@ impl eframe::App for BdiffApp {
                         &mut self.settings.show_quick_access_bar,
                         "Show Quick Access bar",
                     ));
+                    ui.add(Checkbox::new(&mut self.settings.unified_view, "Unified view"));
+                    ui.horizontal(|ui| {
+                        ui.add(egui::DragValue::new(&mut self.settings.max_versions).range(0..=64));
+                        ui.label("Earlier versions kept per file");
+                    });
+                    ui.add(Checkbox::new(
+                        &mut self.hunk_navigator.show,
+                        "Show hunk list",
+                    ));
+                    ui.add(Checkbox::new(
+                        &mut self.diff_stats.show,
+                        "Show diff statistics",
+                    ));
+                    ui.add(Checkbox::new(
+                        &mut self.patch_records.show,
+                        "Show patch records",
+                    ));
+                    ui.add(Checkbox::new(&mut self.minimap.show, "Show minimap"));
+                    ui.add_enabled(
+                        self.minimap.show,
+                        Checkbox::new(
+                            &mut self.minimap.show_boundaries,
+                            "Show symbol boundaries on minimap",
+                        ),
+                    );
 
                     ui.separator();
 
@@ -574,6 +1434,54 @@ impl eframe::App for BdiffApp {
                         }
                     }
                 });
+
+                ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                    let progress = match (&self.align_task, &self.diff_task) {
+                        (Some(task), _) => Some(task.progress()),
+                        (None, Some(task)) => Some(task.progress()),
+                        (None, None) => None,
+                    };
+                    if let Some(progress) = progress {
+                        if ui.button("Cancel").clicked() {
+                            self.align_task = None;
+                            if self.diff_task.take().is_some() {
+                                self.diff_stale = true;
+                            }
+                        } else {
+                            ui.add(
+                                egui::ProgressBar::new(progress)
+                                    .desired_width(120.0)
+                                    .show_percentage(),
+                            );
+                            ui.spinner();
+                        }
+                    } else if self.is_diffing() && self.diff_stale {
+                        if ui
+                            .button("Recalculate")
+                            .on_hover_text("Diff the files again from scratch")
+                            .clicked()
+                        {
+                            self.recalculate_diffs();
+                        }
+                        ui.colored_label(
+                            ui.visuals().warn_fg_color,
+                            "Diff was cancelled, hunks may be out of date",
+                        );
+                    } else if self.is_diffing() {
+                        let relocation_bytes: usize =
+                            self.diff_state.relocations().iter().map(|r| r.len()).sum();
+                        let relocation_text = match relocation_bytes {
+                            0 => String::new(),
+                            n => format!(" (+0x{:X} in relocations)", n),
+                        };
+                        ui.label(format!(
+                            "{} hunks, 0x{:X} bytes differ{}",
+                            self.diff_state.hunks().len(),
+                            self.diff_state.num_diff_bytes(),
+                            relocation_text
+                        ));
+                    }
+                });
             })
         });
 
@@ -586,24 +1494,134 @@ impl eframe::App for BdiffApp {
             });
         }
 
+        // Hunk list
+        let view_rows = match self.file_views.is_empty() {
+            true => None,
+            false => self.view_rows(),
+        };
+        let view_end = match view_rows.as_ref().and_then(|rows| rows.last()) {
+            Some(HexViewRow::Bytes(row_start)) => row_start + self.bytes_per_row,
+            Some(HexViewRow::Folded(fold)) => fold.end,
+            None => self.global_view_pos + self.bytes_per_row * self.num_rows,
+        };
+        let view = self.global_view_pos..view_end;
+        match self.hunk_navigator.display(
+            ctx,
+            &self.diff_state,
+            &self.file_views,
+            view.clone(),
+            self.diff_is_current(),
+        ) {
+            Some(HunkAction::Jump(hunk)) => self.jump_to(hunk.start),
+            // Positions only line up once the diff is done
+            Some(HunkAction::Copy { hunk, from, to }) if self.diff_is_current() => {
+                self.copy_between_files(from, to, &[hunk]);
+            }
+            Some(HunkAction::Copy { .. }) | None => {}
+        }
+
+        if let Some(pos) =
+            self.patch_records
+                .display(ctx, &self.diff_state, &self.file_views, view.clone())
+        {
+            self.jump_to(pos);
+        }
+
+        // Minimap
+        if !self.file_views.is_empty() {
+            let total_len = self.get_furthest_file_pos();
+            if let Some(pos) = self.minimap.display(
+                ctx,
+                &self.diff_state,
+                &self.file_views,
+                total_len,
+                view.clone(),
+            ) {
+                // Center the view on the clicked position
+                self.jump_to(pos.saturating_sub(view.len() / 2));
+            }
+        }
+
+        self.diff_stats
+            .display(ctx, &self.diff_state, &self.file_views);
+
+        // Pick up the result of a background alignment
+        if let Some(task) = &self.align_task {
+            match task.poll() {
+                DiffTaskStatus::Running => ctx.request_repaint(),
+                DiffTaskStatus::Finished(new_positions) => {
+                    self.align_task = None;
+                    self.apply_alignment(*new_positions);
+                }
+                DiffTaskStatus::Aborted => self.align_task = None,
+            }
+        }
+
+        // Pick up the result of a background diff
+        if let Some(task) = &self.diff_task {
+            match task.poll() {
+                DiffTaskStatus::Running => ctx.request_repaint(),
+                DiffTaskStatus::Finished(state) => {
+                    self.diff_state = *state;
+                    self.diff_stats.invalidate();
+                    self.minimap.invalidate();
+                    self.diff_task = None;
+                    self.diff_stale = false;
+                }
+                DiffTaskStatus::Aborted => {
+                    self.diff_task = None;
+                    self.diff_stale = true;
+                }
+            }
+        }
+
         // Reload changed files
         let mut calc_diff = false;
+        let mut reloaded: Vec<(usize, FileData)> = Vec::new();
+        let mut new_reference: Option<(usize, bool)> = None;
 
         // Main panel
-        for fv in self.file_views.iter_mut() {
-            let cur_sel = fv.hv.selection.clone();
-            let can_selection_change = match self.selecting_hv {
-                Some(id) => id == fv.id,
-                None => true,
-            };
+        let prev_selections: Vec<HexViewSelection> = self
+            .file_views
+            .iter()
+            .map(|fv| fv.hv.selection.clone())
+            .collect();
+        let selecting_hv = self.selecting_hv;
+        let can_selection_change = |id: usize| match selecting_hv {
+            Some(selecting) => selecting == id,
+            None => true,
+        };
 
-            fv.show(
+        if self.settings.unified_view {
+            show_unified_view(
                 ctx,
+                &mut self.file_views,
                 &self.settings,
                 &self.diff_state,
-                can_selection_change,
                 self.global_view_pos,
+                view_rows.as_deref(),
+                self.num_rows,
+                can_selection_change,
             );
+        }
+
+        for (fv, cur_sel) in self.file_views.iter_mut().zip(prev_selections) {
+            let was_reference = fv.reference;
+
+            if !self.settings.unified_view {
+                fv.show(
+                    ctx,
+                    &self.settings,
+                    &self.diff_state,
+                    can_selection_change(fv.id),
+                    self.global_view_pos,
+                    view_rows.as_deref(),
+                );
+            }
+
+            if fv.reference != was_reference {
+                new_reference = Some((fv.id, fv.reference));
+            }
 
             if fv.closed {
                 // Remove file from the workspace if it's closed.
@@ -654,6 +1672,23 @@ impl eframe::App for BdiffApp {
             }
         }
 
+        if let Some((id, reference)) = new_reference {
+            self.set_reference(id, reference);
+        }
+
+        // Closing the view of an earlier version turns off diffing against it
+        let closed_version_sources: Vec<usize> = self
+            .file_views
+            .iter()
+            .filter(|fv| fv.closed)
+            .filter_map(|fv| Some(fv.version_view.as_ref()?.source_id))
+            .collect();
+        for fv in self.file_views.iter_mut() {
+            if closed_version_sources.contains(&fv.id) {
+                fv.diff_previous = false;
+            }
+        }
+
         // Delete any closed hex views
         self.file_views.retain(|fv| {
             calc_diff = calc_diff || fv.closed;
@@ -676,23 +1711,44 @@ impl eframe::App for BdiffApp {
         // File reloading
         for fv in self.file_views.iter_mut() {
             if fv.file.modified.swap(false, Ordering::Relaxed) {
-                match fv.reload_file() {
+                let old_data = fv.file.data.clone();
+                // Reloading would throw away edits that haven't been saved yet
+                let result = match fv.file.edits.is_dirty() {
+                    true => Err(Error::msg("It has unsaved edits")),
+                    false => fv.reload_file(self.settings.max_versions),
+                };
+                match result {
                     Ok(_) => {
                         log::info!("Reloaded file {}", fv.file.path.display());
-                        calc_diff = true;
+                        self.toasts.add(fv.reload_summary());
+                        // The old contents of paged files are read from the file as it is now,
+                        // so there's nothing to compare the new contents with
+                        match old_data.is_paged() {
+                            true => calc_diff = true,
+                            false => reloaded.push((fv.id, old_data)),
+                        }
                     }
                     Err(e) => {
                         log::error!("Failed to reload file: {}", e);
+                        self.toasts.add(format!(
+                            "{} changed on disk but wasn't reloaded: {}",
+                            fv.file
+                                .path
+                                .file_name()
+                                .unwrap_or_default()
+                                .to_string_lossy(),
+                            e
+                        ));
                     }
                 }
             }
 
-            if fv.st.map_file.is_some() {
-                let map_file = fv.st.map_file.as_mut().unwrap();
+            if let Some(map_file) = fv.st.map_file.as_mut() {
                 if map_file.modified.swap(false, Ordering::Relaxed) {
                     match map_file.reload() {
                         Ok(_) => {
                             log::info!("Reloaded map file {}", map_file.path.display());
+                            self.diff_stats.invalidate();
                         }
                         Err(e) => {
                             log::error!("Failed to reload map file: {}", e);
@@ -702,15 +1758,412 @@ impl eframe::App for BdiffApp {
             }
         }
 
-        if calc_diff {
+        // Keep fading out the highlight of bytes changed by reloads
+        if self
+            .file_views
+            .iter()
+            .any(|fv| fv.recent_change_strength().is_some())
+        {
+            ctx.request_repaint();
+        }
+        self.toasts.show(ctx);
+
+        reloaded.append(&mut self.edited);
+        let opened_versions = self.sync_version_views(&mut reloaded);
+        if calc_diff || opened_versions {
             self.recalculate_diffs();
+        } else if !reloaded.is_empty() {
+            self.update_diffs_after_reload(reloaded);
         }
     }
 }
 
 impl BdiffApp {
     fn recalculate_diffs(&mut self) {
-        self.diff_state.recalculate(&self.file_views);
+        // Dropping a running task cancels it, as its result would be out of date
+        self.diff_task = None;
+
+        if !self.settings.diff_enabled {
+            self.diff_state = DiffState::default();
+            self.diff_stats.invalidate();
+            self.minimap.invalidate();
+            self.diff_stale = false;
+            return;
+        }
+
+        let inputs = self.diff_inputs();
+        let config = self.diff_config();
+
+        self.diff_task = Some(DiffTask::spawn(move |progress| {
+            DiffState::calculate(&inputs, &config, progress)
+        }));
+    }
+
+    /// Snapshots of the open files, the reference first
+    fn diff_inputs(&self) -> Vec<DiffInput> {
+        let mut inputs: Vec<DiffInput> = self.file_views.iter().map(DiffInput::from).collect();
+        if let Some(idx) = self.file_views.iter().position(|fv| fv.reference) {
+            let reference = inputs.remove(idx);
+            inputs.insert(0, reference);
+        }
+        inputs
+    }
+
+    /// Makes the file view `id` the one the others are diffed against, or stops it being so
+    fn set_reference(&mut self, id: usize, reference: bool) {
+        for fv in self.file_views.iter_mut() {
+            fv.reference = reference && fv.id == id;
+        }
+
+        for file in self.workspace.files.iter_mut() {
+            file.reference = self
+                .file_views
+                .iter()
+                .any(|fv| fv.reference && fv.file.path == file.path);
+        }
+
+        self.recalculate_diffs();
+    }
+
+    /// Starts looking for the position at which the selected file, or every file if none is
+    /// selected, matches the reference best, to shift it there once found. With
+    /// `selection_only`, only the selected bytes are matched.
+    fn auto_align(&mut self, selection_only: bool) {
+        let Some(reference_idx) = self
+            .file_views
+            .iter()
+            .position(|fv| fv.reference)
+            .or((!self.file_views.is_empty()).then_some(0))
+        else {
+            return;
+        };
+        let reference_id = self.file_views[reference_idx].id;
+        let reference_pos = self.file_views[reference_idx].cur_pos as isize;
+        let reference_data = self.file_views[reference_idx].file.data.clone();
+
+        let targets: Vec<usize> = match self.last_selected_hv {
+            Some(id) if id != reference_id => vec![id],
+            _ => self
+                .file_views
+                .iter()
+                .map(|fv| fv.id)
+                .filter(|&id| id != reference_id)
+                .collect(),
+        };
+
+        // The bytes of each file to match, and where they start in it
+        let mut jobs: Vec<(usize, FileData, Range<usize>)> = Vec::new();
+        for fv in self.file_views.iter().filter(|fv| targets.contains(&fv.id)) {
+            let data = &fv.file.data;
+            if data.is_empty() {
+                continue;
+            }
+
+            let selected = selection_only && fv.hv.selection.state != HexViewSelectionState::None;
+            let range = match selected {
+                true => {
+                    let start = fv.hv.selection.start().saturating_sub(fv.cur_pos);
+                    let start = start.min(data.len() - 1);
+                    let end = (fv.hv.selection.end() + 1).saturating_sub(fv.cur_pos);
+                    start..end.clamp(start + 1, data.len())
+                }
+                false => 0..data.len(),
+            };
+            jobs.push((fv.id, data.clone(), range));
+        }
+
+        self.align_task = Some(DiffTask::spawn(move |progress| {
+            progress.add_total(jobs.iter().map(|(_, _, range)| range.len()).sum());
+            // Matching looks all over the reference, so it's read in whole
+            let reference_data = reference_data.to_vec();
+
+            let mut new_positions: AlignedPositions = Vec::new();
+            for (id, data, range) in jobs {
+                if progress.is_cancelled() {
+                    return None;
+                }
+
+                let shift = best_shift(&reference_data, &data.read(range.clone()));
+                new_positions.push((id, shift.map(|s| reference_pos + s - range.start as isize)));
+                progress.advance(range.len());
+            }
+            Some(new_positions)
+        }));
+    }
+
+    /// Moves file views to the positions found by auto-aligning them, telling about the ones that
+    /// couldn't be aligned
+    fn apply_alignment(&mut self, new_positions: AlignedPositions) {
+        let mut unmatched = Vec::new();
+        let new_positions: Vec<(usize, isize)> = new_positions
+            .into_iter()
+            .filter_map(|(id, pos)| {
+                if pos.is_none() {
+                    unmatched.push(id);
+                }
+                Some((id, pos?))
+            })
+            .collect();
+        for fv in self
+            .file_views
+            .iter()
+            .filter(|fv| unmatched.contains(&fv.id))
+        {
+            self.toasts.add(format!(
+                "No matching bytes found to align {}",
+                fv.file_name()
+            ));
+        }
+        if new_positions.is_empty() {
+            return;
+        }
+
+        // Files can't start before position 0, so move everything else down instead
+        let lowest_new_pos = new_positions.iter().map(|(_, pos)| *pos).min().unwrap();
+        let raise = (-lowest_new_pos).max(0);
+        for fv in self.file_views.iter_mut() {
+            match new_positions.iter().find(|(id, _)| *id == fv.id) {
+                Some((_, pos)) => fv.cur_pos = (pos + raise) as usize,
+                None => fv.cur_pos += raise as usize,
+            }
+        }
+
+        // Keep positions zeroed
+        let lowest_fv_pos = self.file_views.iter().map(|fv| fv.cur_pos).min().unwrap();
+        for fv in self.file_views.iter_mut() {
+            fv.cur_pos -= lowest_fv_pos;
+        }
+
+        self.recalculate_diffs();
+    }
+
+    /// Asks where to save a patch that turns the reference into the selected file, or into the
+    /// first other file if the reference is selected
+    fn export_patch(&self, format: PatchFormat) {
+        let Some(source) = self.reference_view() else {
+            return;
+        };
+        let Some(target) = self
+            .file_views
+            .iter()
+            .find(|fv| fv.id != source.id && Some(fv.id) == self.last_selected_hv)
+            .or_else(|| self.file_views.iter().find(|fv| fv.id != source.id))
+        else {
+            return;
+        };
+
+        let file_name = match target.file.path.file_stem() {
+            Some(stem) => format!("{}.{}", stem.to_string_lossy(), format.extension()),
+            None => format!("patch.{}", format.extension()),
+        };
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter(format.to_string(), &[format.extension()])
+            .set_file_name(file_name)
+            .save_file()
+        else {
+            return;
+        };
+
+        let source_input = DiffInput::from(source);
+        let target_input = DiffInput::from(target);
+
+        // The hunks shown can be used if they're up to date and compare the files byte for byte
+        let ranges = match self.is_diffing() && self.diff_is_current() {
+            true => self.diff_state.patch_ranges(&target_input),
+            false => None,
+        }
+        .unwrap_or_else(|| positional_patch_ranges(&source_input, &target_input));
+
+        match create_patch(format, &source.file.data, &target.file.data, &ranges)
+            .and_then(|patch| std::fs::write(&path, patch).map_err(Error::new))
+        {
+            Ok(()) => log::info!("Wrote {} patch to {}", format, path.display()),
+            Err(e) => log::error!("Failed to export patch: {}", e),
+        }
+    }
+
+    fn diff_config(&self) -> DiffConfig {
+        DiffConfig {
+            mode: self.settings.diff_mode,
+            basis: self.settings.deviation_basis,
+            ignored: self.ignored_ranges(),
+        }
+    }
+
+    fn reference_view(&self) -> Option<&FileView> {
+        self.file_views
+            .iter()
+            .find(|fv| fv.reference)
+            .or(self.file_views.first())
+    }
+
+    /// The workspace's ignore regions as ranges of reference file offsets
+    fn ignored_ranges(&self) -> Vec<Range<usize>> {
+        let map_file = self.reference_view().and_then(|fv| fv.st.map_file.as_ref());
+
+        self.workspace
+            .ignore
+            .iter()
+            .filter_map(|region| match region {
+                IgnoreRegion::Range { offset, length } => Some(*offset..offset + length),
+                IgnoreRegion::Symbol { symbol } => {
+                    match map_file.and_then(|mf| mf.find_symbol(symbol)) {
+                        Some(entry) => {
+                            Some(entry.symbol_vrom..entry.symbol_vrom + entry.symbol_size)
+                        }
+                        None => {
+                            log::warn!("Symbol {} to ignore was not found", symbol);
+                            None
+                        }
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Leaves the bytes of the reference file that are currently selected out of diffs
+    fn ignore_selection(&mut self) {
+        if self.global_selection.state == HexViewSelectionState::None {
+            return;
+        }
+        let Some(fv) = self.reference_view() else {
+            return;
+        };
+
+        let gaps = self.diff_state.gaps(fv.id);
+        let to_file = |pos: usize| {
+            pos.checked_sub(fv.cur_pos)
+                .map(|view_pos| view_pos - gaps.map_or(0, |g| gap_bytes_before(view_pos, g)))
+        };
+        let start = to_file(self.global_selection.start()).unwrap_or(0);
+        let end = to_file(self.global_selection.end() + 1)
+            .unwrap_or(0)
+            .min(fv.file.data.len());
+
+        if start < end {
+            self.workspace.ignore.push(IgnoreRegion::Range {
+                offset: start,
+                length: end - start,
+            });
+            self.recalculate_diffs();
+        }
+    }
+
+    /// Opens and closes the views of earlier file versions as their files ask for them, and points
+    /// each at the version picked on it. Views whose contents change are added to `reloaded`.
+    /// Returns whether any view was opened.
+    fn sync_version_views(&mut self, reloaded: &mut Vec<(usize, FileData)>) -> bool {
+        let wanted: Vec<usize> = self
+            .file_views
+            .iter()
+            .filter(|fv| fv.diff_previous && !fv.history.is_empty())
+            .map(|fv| fv.id)
+            .filter(|&id| {
+                !self
+                    .file_views
+                    .iter()
+                    .any(|fv| fv.version_view.as_ref().is_some_and(|v| v.source_id == id))
+            })
+            .collect();
+
+        for &id in wanted.iter() {
+            let source = self.file_views.iter().find(|fv| fv.id == id).unwrap();
+            let version = source.history.back().unwrap();
+            let file = BinFile::from_data(
+                format!("{} (previous build)", source.file.path.display()).into(),
+                version.data.clone(),
+                source.file.endianness,
+            );
+
+            let mut fv = FileView::new(file, self.next_hv_id, self.bytes_per_row, self.num_rows);
+            fv.cur_pos = source.cur_pos;
+            fv.version_view = Some(VersionView {
+                source_id: id,
+                versions_back: 1,
+                available: source.history.len(),
+                loaded: version.loaded,
+            });
+            self.file_views.push(fv);
+            self.next_hv_id += 1;
+        }
+
+        for idx in 0..self.file_views.len() {
+            let Some(source_id) = self.file_views[idx]
+                .version_view
+                .as_ref()
+                .map(|v| v.source_id)
+            else {
+                continue;
+            };
+            let source = self
+                .file_views
+                .iter()
+                .find(|fv| fv.id == source_id && fv.diff_previous && !fv.history.is_empty());
+            let Some(history) = source.map(|fv| &fv.history) else {
+                self.file_views[idx].closed = true;
+                continue;
+            };
+
+            let fv = &self.file_views[idx];
+            let version = fv.version_view.as_ref().unwrap();
+            let versions_back = version.versions_back.clamp(1, history.len());
+            let shown = &history[history.len() - versions_back];
+            let (data, loaded, available) = (shown.data.clone(), shown.loaded, history.len());
+
+            let fv = &mut self.file_views[idx];
+            let version = fv.version_view.as_mut().unwrap();
+            version.versions_back = versions_back;
+            version.available = available;
+            version.loaded = loaded;
+            if !FileData::ptr_eq(&fv.file.data, &data) {
+                reloaded.push((fv.id, std::mem::replace(&mut fv.file.data, data)));
+            }
+        }
+
+        !wanted.is_empty()
+    }
+
+    /// Re-diffs only the bytes that changed in reloaded or edited files, falling back to a full
+    /// recalculation when the layout of the files might have changed
+    fn update_diffs_after_reload(&mut self, reloaded: Vec<(usize, FileData)>) {
+        let same_lengths = reloaded.iter().all(|(id, old_data)| {
+            self.file_views
+                .iter()
+                .any(|fv| fv.id == *id && fv.file.data.len() == old_data.len())
+        });
+
+        if self.diff_task.is_some()
+            || self.diff_stale
+            || !self.settings.diff_enabled
+            || self.settings.diff_mode != DiffMode::Positional
+            || self.diff_state.has_gaps()
+            || !same_lengths
+        {
+            self.recalculate_diffs();
+            return;
+        }
+
+        let inputs = self.diff_inputs();
+        let base = self.diff_state.clone();
+        let config = self.diff_config();
+
+        self.diff_task = Some(DiffTask::spawn(move |progress| {
+            let changed: Vec<Range<usize>> = reloaded
+                .iter()
+                .filter_map(|(id, old_data)| {
+                    let input = inputs.iter().find(|i| i.id == *id)?;
+                    Some(
+                        changed_ranges(old_data, &input.data)
+                            .into_iter()
+                            .map(|r| r.start + input.cur_pos..r.end + input.cur_pos)
+                            .collect::<Vec<_>>(),
+                    )
+                })
+                .flatten()
+                .collect();
+
+            base.recalculate_ranges(&inputs, &changed, &config, progress)
+        }));
     }
 
     fn show_overwrite_modal(&mut self, modal: &Modal) {
@@ -1,14 +1,25 @@
 use std::{
+    borrow::Cow,
+    collections::{HashMap, VecDeque},
     fs::File,
-    io::{BufReader, Read},
-    path::PathBuf,
-    sync::{atomic::AtomicBool, Arc},
+    io::{self, BufReader, Read, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{atomic::AtomicBool, Arc, Mutex},
 };
 
-use crate::watcher::create_watcher;
+use crate::{edit::EditLayer, watcher::create_watcher};
 use anyhow::Error;
+use bdiff_hex_view::ByteSource;
 use serde::{Deserialize, Serialize};
 
+/// Files at least this large are read from disk as their bytes are needed instead of all at once
+const PAGED_THRESHOLD: u64 = 64 * 1024 * 1024;
+/// Size of the pieces large files are read in
+const PAGE_SIZE: usize = 0x10000;
+/// Number of pages of each large file kept in memory
+const MAX_CACHED_PAGES: usize = 1024;
+
 #[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Endianness {
@@ -17,11 +28,350 @@ pub enum Endianness {
     Big,
 }
 
+/// Reads from `offset` until `buf` is full, failing if the file ends first
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        #[cfg(unix)]
+        let read = std::os::unix::fs::FileExt::read_at(file, buf, offset);
+        #[cfg(windows)]
+        let read = std::os::windows::fs::FileExt::seek_read(file, buf, offset);
+
+        match read {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct PageCache {
+    pages: HashMap<usize, Arc<[u8]>>,
+    /// Indices of the cached pages, in the order they were read
+    order: VecDeque<usize>,
+}
+
+/// A file read a page at a time, keeping the pages read most recently in memory
+struct PagedFile {
+    file: File,
+    len: usize,
+    cache: Mutex<PageCache>,
+}
+
+impl PagedFile {
+    /// The page at `idx`, reading it from the file if it isn't cached
+    fn page(&self, idx: usize) -> Arc<[u8]> {
+        if let Some(page) = self.cache.lock().unwrap().pages.get(&idx) {
+            return page.clone();
+        }
+
+        let start = idx * PAGE_SIZE;
+        let mut page = vec![0; PAGE_SIZE.min(self.len - start)];
+        if let Err(e) = read_exact_at(&self.file, &mut page, start as u64) {
+            // The file was changed under us. The rest of the page reads as zeroes until the
+            // watcher notices the change and the file is opened again.
+            log::warn!(
+                "Failed to read 0x{:X} bytes at 0x{:X}: {e}",
+                page.len(),
+                start
+            );
+        }
+        let page: Arc<[u8]> = page.into();
+
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(cached) = cache.pages.get(&idx) {
+            return cached.clone();
+        }
+        if cache.order.len() >= MAX_CACHED_PAGES {
+            if let Some(oldest) = cache.order.pop_front() {
+                cache.pages.remove(&oldest);
+            }
+        }
+        cache.pages.insert(idx, page.clone());
+        cache.order.push_back(idx);
+        page
+    }
+}
+
+/// Ranges of other contents one after another, like those of a file with edits made to it
+struct Joined {
+    /// Contents that aren't joined themselves, with the range of each that's used. None of the
+    /// ranges are empty.
+    parts: Vec<(FileData, Range<usize>)>,
+    /// Offset at which each part starts
+    starts: Vec<usize>,
+    len: usize,
+}
+
+impl Joined {
+    /// Index of the part containing `offset`, which must be within the contents
+    fn part_at(&self, offset: usize) -> usize {
+        self.starts.partition_point(|&start| start <= offset) - 1
+    }
+
+    /// The parts with bytes in `range`, each with the range of its contents that lies within it
+    fn parts_in(&self, range: Range<usize>) -> impl Iterator<Item = (&FileData, Range<usize>)> {
+        let first = match range.is_empty() {
+            true => self.parts.len(),
+            false => self.part_at(range.start),
+        };
+        self.parts[first..]
+            .iter()
+            .zip(&self.starts[first..])
+            .take_while(move |(_, &start)| start < range.end)
+            .map(move |((data, part_range), &start)| {
+                let from = range.start.max(start) - start + part_range.start;
+                let to = range.end.min(start + part_range.len()) - start + part_range.start;
+                (data, from..to)
+            })
+    }
+}
+
+enum FileBytes {
+    Owned(Box<[u8]>),
+    Paged(PagedFile),
+    Joined(Joined),
+}
+
+/// The contents of a file. Large files are read from disk a page at a time as their bytes are
+/// needed, so they don't have to fit in memory. The file is only read from, never mapped, so
+/// other programs can still truncate, overwrite or replace it. Clones share the same bytes.
+#[derive(Clone)]
+pub struct FileData(Arc<FileBytes>);
+
+impl FileData {
+    /// Opens the file at `path`, reading it all if it's small and only as needed otherwise
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let file = File::open(path).map_err(|_| Error::msg("Failed to open file"))?;
+        let len = file.metadata()?.len();
+
+        if len < PAGED_THRESHOLD {
+            return Ok(read_file_bytes(path)?.into());
+        }
+
+        let len = usize::try_from(len).map_err(|_| Error::msg("File is too large"))?;
+        Ok(Self(Arc::new(FileBytes::Paged(PagedFile {
+            file,
+            len,
+            cache: Mutex::default(),
+        }))))
+    }
+
+    /// Contents made of the given ranges of other contents, one after another
+    pub fn join(parts: impl IntoIterator<Item = (FileData, Range<usize>)>) -> Self {
+        let mut flat = Vec::new();
+        for (data, range) in parts {
+            data.push_parts(range, &mut flat);
+        }
+
+        match &flat[..] {
+            [] => return Self::default(),
+            [(data, range)] if *range == (0..data.len()) => return data.clone(),
+            _ => {}
+        }
+
+        let mut starts = Vec::with_capacity(flat.len());
+        let mut len = 0;
+        for (_, range) in flat.iter() {
+            starts.push(len);
+            len += range.len();
+        }
+        Self(Arc::new(FileBytes::Joined(Joined {
+            parts: flat,
+            starts,
+            len,
+        })))
+    }
+
+    /// Appends the contents that make up `range` and aren't joined themselves, with the range
+    /// of each that's used
+    fn push_parts(&self, range: Range<usize>, parts: &mut Vec<(FileData, Range<usize>)>) {
+        if range.is_empty() {
+            return;
+        }
+
+        match &*self.0 {
+            FileBytes::Joined(joined) => parts.extend(
+                joined
+                    .parts_in(range)
+                    .map(|(data, range)| (data.clone(), range)),
+            ),
+            _ => parts.push((self.clone(), range)),
+        }
+    }
+
+    /// The contents that make these up and aren't joined themselves, in order, with the range
+    /// of each that's used
+    pub fn parts(&self) -> Vec<(FileData, Range<usize>)> {
+        let mut ret = Vec::new();
+        self.push_parts(0..self.len(), &mut ret);
+        ret
+    }
+
+    pub fn len(&self) -> usize {
+        match &*self.0 {
+            FileBytes::Owned(bytes) => bytes.len(),
+            FileBytes::Paged(paged) => paged.len,
+            FileBytes::Joined(joined) => joined.len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, offset: usize) -> Option<u8> {
+        match &*self.0 {
+            FileBytes::Owned(bytes) => bytes.get(offset).copied(),
+            FileBytes::Paged(paged) => {
+                (offset < paged.len).then(|| paged.page(offset / PAGE_SIZE)[offset % PAGE_SIZE])
+            }
+            FileBytes::Joined(joined) => {
+                if offset >= joined.len {
+                    return None;
+                }
+                let idx = joined.part_at(offset);
+                let (data, range) = &joined.parts[idx];
+                data.get(range.start + offset - joined.starts[idx])
+            }
+        }
+    }
+
+    /// Calls `f` with the bytes in `range`, which must lie within the contents, a piece at a time
+    pub fn for_each_chunk(&self, range: Range<usize>, f: &mut dyn FnMut(&[u8])) {
+        self.check_range(&range);
+
+        match &*self.0 {
+            FileBytes::Owned(bytes) => f(&bytes[range]),
+            FileBytes::Paged(paged) => {
+                let mut offset = range.start;
+                while offset < range.end {
+                    let page = paged.page(offset / PAGE_SIZE);
+                    let page_start = offset / PAGE_SIZE * PAGE_SIZE;
+                    let end = range.end.min(page_start + page.len());
+                    f(&page[offset - page_start..end - page_start]);
+                    offset = end;
+                }
+            }
+            FileBytes::Joined(joined) => {
+                for (data, range) in joined.parts_in(range) {
+                    data.for_each_chunk(range, f);
+                }
+            }
+        }
+    }
+
+    /// The bytes in `range`, which must lie within the contents. They're only copied if they
+    /// aren't held in memory in one piece.
+    pub fn read(&self, range: Range<usize>) -> Cow<'_, [u8]> {
+        self.check_range(&range);
+        match &*self.0 {
+            FileBytes::Owned(bytes) => return Cow::Borrowed(&bytes[range]),
+            FileBytes::Joined(joined) if !range.is_empty() => {
+                let mut parts = joined.parts_in(range.clone());
+                if let (Some((data, range)), None) = (parts.next(), parts.next()) {
+                    return data.read(range);
+                }
+            }
+            _ => {}
+        }
+
+        let mut ret = Vec::with_capacity(range.len());
+        self.for_each_chunk(range, &mut |chunk| ret.extend_from_slice(chunk));
+        Cow::Owned(ret)
+    }
+
+    fn check_range(&self, range: &Range<usize>) {
+        assert!(
+            range.start <= range.end && range.end <= self.len(),
+            "range 0x{:X}..0x{:X} is out of bounds of 0x{:X} bytes",
+            range.start,
+            range.end,
+            self.len()
+        );
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.read(0..self.len()).into_owned()
+    }
+
+    /// Whether any of the bytes are read from a file as they're needed rather than held in
+    /// memory, so they change along with the file
+    pub fn is_paged(&self) -> bool {
+        match &*self.0 {
+            FileBytes::Owned(_) => false,
+            FileBytes::Paged(_) => true,
+            FileBytes::Joined(joined) => joined.parts.iter().any(|(data, _)| data.is_paged()),
+        }
+    }
+
+    /// Whether `a` and `b` share the same bytes
+    pub fn ptr_eq(a: &Self, b: &Self) -> bool {
+        Arc::ptr_eq(&a.0, &b.0)
+    }
+}
+
+impl ByteSource for FileData {
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn get(&self, offset: usize) -> Option<u8> {
+        self.get(offset)
+    }
+}
+
+impl From<Vec<u8>> for FileData {
+    fn from(value: Vec<u8>) -> Self {
+        Self(Arc::new(FileBytes::Owned(value.into_boxed_slice())))
+    }
+}
+
+impl Default for FileData {
+    fn default() -> Self {
+        Vec::new().into()
+    }
+}
+
+impl PartialEq for FileData {
+    fn eq(&self, other: &Self) -> bool {
+        if Self::ptr_eq(self, other) {
+            return true;
+        }
+        if self.len() != other.len() {
+            return false;
+        }
+
+        (0..self.len()).step_by(PAGE_SIZE).all(|start| {
+            let range = start..(start + PAGE_SIZE).min(self.len());
+            self.read(range.clone()) == other.read(range)
+        })
+    }
+}
+
+impl std::fmt::Debug for FileData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileData")
+            .field("len", &self.len())
+            .field("paged", &self.is_paged())
+            .finish()
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct BinFile {
     pub path: PathBuf,
-    pub data: Vec<u8>,
+    pub data: FileData,
     pub endianness: Endianness,
+    /// Changes made to `data` in the editor that can be undone
+    pub edits: EditLayer,
+    /// Whether the file only exists in memory, so `path` only describes it
+    in_memory: bool,
     watcher: Option<notify::RecommendedWatcher>,
     pub modified: Arc<AtomicBool>,
 }
@@ -47,25 +397,149 @@ pub fn read_file_bytes<P: Into<PathBuf>>(path: P) -> Result<Vec<u8>, Error> {
 impl BinFile {
     pub fn from_path<P: Into<PathBuf>>(path: P) -> Result<Self, Error> {
         let path: PathBuf = path.into();
-        let data = match read_file_bytes(&path) {
-            Ok(data) => data,
-            Err(e) => return Err(e),
-        };
+        let data = FileData::open(&path)?;
 
         let mut ret = Self {
-            path: path.clone(),
+            path,
             data,
             watcher: None,
             ..Default::default()
         };
+        ret.watch();
+
+        Ok(ret)
+    }
 
-        match create_watcher(path, ret.modified.clone()).map_err(Error::new) {
+    /// A file that only exists in memory, such as the result of applying a patch
+    pub fn from_data(path: PathBuf, data: FileData, endianness: Endianness) -> Self {
+        Self {
+            path,
+            data,
+            endianness,
+            in_memory: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_in_memory(&self) -> bool {
+        self.in_memory
+    }
+
+    /// Watches the file for changes. The flag set by changes is replaced, so events still coming
+    /// from an earlier watcher, like those caused by saving the file, are not picked up.
+    fn watch(&mut self) {
+        self.modified = Arc::new(AtomicBool::new(false));
+        self.watcher = None;
+
+        match create_watcher(self.path.clone(), self.modified.clone()).map_err(Error::new) {
             Ok(watcher) => {
-                ret.watcher = Some(watcher);
+                self.watcher = Some(watcher);
             }
             Err(e) => log::error!("Failed to create watcher: {e}"),
         }
+    }
 
-        Ok(ret)
+    /// Replaces each range with the bytes given, in order, as a single step that can be undone.
+    /// Returns whether anything changed.
+    pub fn replace(&mut self, replacements: &[(Range<usize>, &[u8])]) -> bool {
+        self.edits.replace(&mut self.data, replacements)
+    }
+
+    pub fn insert(&mut self, offset: usize, bytes: &[u8]) -> bool {
+        self.replace(&[(offset..offset, bytes)])
+    }
+
+    pub fn delete(&mut self, range: Range<usize>) -> bool {
+        self.replace(&[(range, &[])])
+    }
+
+    pub fn undo(&mut self) -> bool {
+        self.edits.undo(&mut self.data)
+    }
+
+    pub fn redo(&mut self) -> bool {
+        self.edits.redo(&mut self.data)
+    }
+
+    /// Writes the contents back to the file
+    pub fn save(&mut self) -> Result<(), Error> {
+        let path = self.path.clone();
+        self.save_as(&path)
+    }
+
+    /// Writes the contents to `path` and makes it the file's path. The contents go to a temporary
+    /// file first, which then replaces the file, so a failed save can't leave it half written.
+    pub fn save_as(&mut self, path: &Path) -> Result<(), Error> {
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| Error::msg("No file name to save to"))?;
+        let temp_path = path.with_file_name(format!(".{}.bdiff-tmp", file_name.to_string_lossy()));
+
+        let write_temp = || -> Result<(), Error> {
+            let mut file = File::create(&temp_path)?;
+            let mut result = Ok(());
+            self.data.for_each_chunk(0..self.data.len(), &mut |chunk| {
+                if result.is_ok() {
+                    result = file.write_all(chunk);
+                }
+            });
+            result?;
+            file.sync_all()?;
+            if let Ok(metadata) = std::fs::metadata(path) {
+                std::fs::set_permissions(&temp_path, metadata.permissions())?;
+            }
+            std::fs::rename(&temp_path, path)?;
+            Ok(())
+        };
+        if let Err(e) = write_temp() {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(e);
+        }
+
+        self.path = path.to_owned();
+        self.in_memory = false;
+        self.edits.mark_saved();
+
+        // Replacing the file ends the watch on it, so start a new one
+        self.watch();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paged_reads_across_page_boundaries() {
+        let bytes: Vec<u8> = (0..PAGE_SIZE * 2 + 0x100)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let path = std::env::temp_dir().join(format!("bdiff-paged-{}.bin", std::process::id()));
+        std::fs::write(&path, &bytes).unwrap();
+
+        let data = FileData(Arc::new(FileBytes::Paged(PagedFile {
+            file: File::open(&path).unwrap(),
+            len: bytes.len(),
+            cache: Mutex::default(),
+        })));
+
+        assert!(data.is_paged());
+        assert_eq!(data.len(), bytes.len());
+        for range in [
+            PAGE_SIZE - 0x10..PAGE_SIZE + 0x10,
+            PAGE_SIZE - 1..PAGE_SIZE * 2 + 1,
+            0..bytes.len(),
+        ] {
+            assert_eq!(data.read(range.clone()), &bytes[range]);
+        }
+        assert_eq!(
+            data.get(PAGE_SIZE * 2 + 0xFF),
+            Some(bytes[PAGE_SIZE * 2 + 0xFF])
+        );
+        assert_eq!(data.get(bytes.len()), None);
+
+        drop(data);
+        std::fs::remove_file(&path).unwrap();
     }
 }
@@ -0,0 +1,461 @@
+use std::{
+    collections::HashSet,
+    ops::Range,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    str::FromStr,
+};
+
+use anyhow::{Context, Error};
+use argh::FromArgs;
+use bdiff_hex_view::view_to_file_offset;
+use serde::Serialize;
+
+use crate::{
+    bin_file::{Endianness, FileData},
+    diff_state::{DeviationBasis, DiffConfig, DiffInput, DiffMode, DiffProgress, DiffState},
+    patch::{create_patch, positional_patch_ranges, PatchFormat},
+    tools::map_file::MapFile,
+};
+
+/// Number of bytes per file shown for a hunk in text output
+const TEXT_BYTES_LIMIT: usize = 32;
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum Command {
+    Diff(DiffArgs),
+    FirstDiff(FirstDiffArgs),
+    MakePatch(MakePatchArgs),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("unknown format \"{}\", expected text or json", s)),
+        }
+    }
+}
+
+fn parse_diff_mode(s: &str) -> Result<DiffMode, String> {
+    match s {
+        "positional" => Ok(DiffMode::Positional),
+        "aligned" => Ok(DiffMode::Aligned),
+        "mips" => Ok(DiffMode::Mips),
+        _ => Err(format!(
+            "unknown mode \"{}\", expected positional, aligned or mips",
+            s
+        )),
+    }
+}
+
+#[derive(FromArgs)]
+/// diff two files without opening the interface, exiting with 1 if they differ
+#[argh(subcommand, name = "diff")]
+pub struct DiffArgs {
+    /// reference file
+    #[argh(positional)]
+    a: PathBuf,
+
+    /// file to compare against the reference
+    #[argh(positional)]
+    b: PathBuf,
+
+    /// map file of the reference, to name the symbol each hunk is in
+    #[argh(option)]
+    map: Option<PathBuf>,
+
+    /// output format: text (default) or json
+    #[argh(option, default = "OutputFormat::Text")]
+    format: OutputFormat,
+
+    /// diff mode: positional (default), aligned or mips
+    #[argh(option, default = "DiffMode::Positional", from_str_fn(parse_diff_mode))]
+    mode: DiffMode,
+
+    /// treat the files as little-endian (only matters in mips mode)
+    #[argh(switch)]
+    little_endian: bool,
+}
+
+#[derive(FromArgs)]
+/// report the first differing words of a built ROM with the symbols they are in, exiting with 1
+/// if the ROM doesn't match
+#[argh(subcommand, name = "first-diff")]
+pub struct FirstDiffArgs {
+    /// the original ROM
+    #[argh(positional)]
+    baserom: PathBuf,
+
+    /// the built ROM
+    #[argh(positional)]
+    rom: PathBuf,
+
+    /// map file of the built ROM
+    #[argh(positional)]
+    map: PathBuf,
+
+    /// number of differing words to report (default 10)
+    #[argh(option, short = 'n', default = "10")]
+    count: usize,
+}
+
+#[derive(FromArgs)]
+/// write a patch that turns the original file into the modified one
+#[argh(subcommand, name = "make-patch")]
+pub struct MakePatchArgs {
+    /// the original file
+    #[argh(positional)]
+    source: PathBuf,
+
+    /// the modified file
+    #[argh(positional)]
+    target: PathBuf,
+
+    /// path to write the patch to
+    #[argh(positional)]
+    output: PathBuf,
+
+    /// patch format: ips, bps or ups (default: from the output's extension, otherwise ips)
+    #[argh(option)]
+    format: Option<PatchFormat>,
+}
+
+#[derive(Serialize)]
+struct HunkReport {
+    /// Offset into the reference file, or the position in the diff if the reference has no bytes there
+    offset: usize,
+    length: usize,
+    /// The bytes each file has in the hunk, or `None` if it has none there
+    a: Option<String>,
+    b: Option<String>,
+    symbol: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DiffReport {
+    hunks: Vec<HunkReport>,
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// The bytes `input` shows within the global `range`, leaving out gaps
+fn bytes_in_range(diff_state: &DiffState, input: &DiffInput, range: Range<usize>) -> Vec<u8> {
+    range
+        .filter_map(|pos| {
+            let offset =
+                view_to_file_offset(pos.checked_sub(input.cur_pos)?, diff_state.gaps(input.id))?;
+            input.data.get(offset)
+        })
+        .collect()
+}
+
+/// The global ranges in which `inputs` differ, ending with the bytes some files have past the
+/// end of the others
+fn differing_ranges(diff_state: &DiffState, inputs: &[DiffInput]) -> Vec<Range<usize>> {
+    diff_state
+        .hunks()
+        .iter()
+        .cloned()
+        .chain(diff_state.length_mismatch(inputs))
+        .collect()
+}
+
+fn hunk_reports(
+    diff_state: &DiffState,
+    inputs: &[DiffInput; 2],
+    map_file: Option<&MapFile>,
+) -> Vec<HunkReport> {
+    differing_ranges(diff_state, inputs)
+        .into_iter()
+        .map(|hunk| {
+            let reference_offset = diff_state.reference_offset(hunk.start);
+            let symbol = reference_offset.and_then(|offset| symbol_text(map_file?, offset));
+            let bytes = |input: &DiffInput| {
+                let bytes = bytes_in_range(diff_state, input, hunk.clone());
+                (!bytes.is_empty()).then(|| hex_string(&bytes))
+            };
+
+            HunkReport {
+                offset: reference_offset.unwrap_or(hunk.start),
+                length: hunk.len(),
+                a: bytes(&inputs[0]),
+                b: bytes(&inputs[1]),
+                symbol,
+            }
+        })
+        .collect()
+}
+
+fn load_input(id: usize, path: &Path, endianness: Endianness) -> Result<DiffInput, Error> {
+    let data =
+        FileData::open(path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+    Ok(DiffInput {
+        id,
+        cur_pos: 0,
+        data,
+        endianness,
+    })
+}
+
+fn run_diff(args: &DiffArgs) -> Result<bool, Error> {
+    let endianness = match args.little_endian {
+        true => Endianness::Little,
+        false => Endianness::Big,
+    };
+    let inputs = [
+        load_input(0, &args.a, endianness)?,
+        load_input(1, &args.b, endianness)?,
+    ];
+    let map_file = args.map.clone().map(MapFile::from_path).transpose()?;
+
+    let config = DiffConfig {
+        mode: args.mode,
+        basis: DeviationBasis::Reference,
+        ignored: Vec::new(),
+    };
+    let diff_state = DiffState::calculate(&inputs, &config, &DiffProgress::default())
+        .ok_or_else(|| Error::msg("Diff was cancelled"))?;
+
+    let hunks = hunk_reports(&diff_state, &inputs, map_file.as_ref());
+    let differ = !hunks.is_empty();
+
+    match args.format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&DiffReport { hunks })?),
+        OutputFormat::Text => {
+            let truncate = |hex: &Option<String>| match hex {
+                Some(hex) if hex.len() > TEXT_BYTES_LIMIT * 3 => {
+                    format!("{} ...", &hex[..TEXT_BYTES_LIMIT * 3 - 1])
+                }
+                Some(hex) => hex.clone(),
+                None => "(no bytes)".to_owned(),
+            };
+
+            for hunk in hunks.iter() {
+                match &hunk.symbol {
+                    Some(symbol) => {
+                        println!("0x{:08X} (len 0x{:X}) {}", hunk.offset, hunk.length, symbol)
+                    }
+                    None => println!("0x{:08X} (len 0x{:X})", hunk.offset, hunk.length),
+                }
+                println!("  a: {}", truncate(&hunk.a));
+                println!("  b: {}", truncate(&hunk.b));
+            }
+
+            match differ {
+                true => println!(
+                    "{} hunks, 0x{:X} bytes differ",
+                    hunks.len(),
+                    hunks.iter().map(|h| h.length).sum::<usize>()
+                ),
+                false => println!("Files are identical"),
+            }
+        }
+    }
+
+    Ok(differ)
+}
+
+/// Name of the symbol containing `offset` and the offset into it, like `func_80012345 + 0x1C`
+fn symbol_text(map_file: &MapFile, offset: usize) -> Option<String> {
+    let entry = map_file.get_entry(offset, offset + 1)?;
+    Some(format!(
+        "{} + 0x{:X}",
+        entry.symbol_name,
+        offset - entry.symbol_vrom
+    ))
+}
+
+fn run_first_diff(args: &FirstDiffArgs) -> Result<bool, Error> {
+    let inputs = [
+        load_input(0, &args.baserom, Endianness::Big)?,
+        load_input(1, &args.rom, Endianness::Big)?,
+    ];
+    let map_file = MapFile::from_path(args.map.clone())?;
+
+    let config = DiffConfig {
+        mode: DiffMode::Positional,
+        basis: DeviationBasis::Reference,
+        ignored: Vec::new(),
+    };
+    let diff_state = DiffState::calculate(&inputs, &config, &DiffProgress::default())
+        .ok_or_else(|| Error::msg("Diff was cancelled"))?;
+
+    // A ROM that's only truncated or padded has no hunks, but still doesn't match
+    let hunks = differing_ranges(&diff_state, &inputs);
+    if hunks.is_empty() {
+        println!("No differences");
+        return Ok(false);
+    }
+
+    let (baserom, rom) = (&inputs[0].data, &inputs[1].data);
+    if baserom.len() != rom.len() {
+        println!(
+            "Size differs: expected 0x{:X} bytes, got 0x{:X}",
+            baserom.len(),
+            rom.len()
+        );
+    }
+
+    // Differing bytes are reported by the word containing them, as most are in code
+    let mut words: Vec<usize> = Vec::new();
+    for pos in hunks.iter().flat_map(|h| h.clone()) {
+        let word = pos - pos % 4;
+        if words.last() != Some(&word) {
+            if words.len() == args.count {
+                break;
+            }
+            words.push(word);
+        }
+    }
+
+    let word_text = |data: &FileData, word: usize| -> String {
+        (word..word + 4)
+            .map(|pos| match data.get(pos) {
+                Some(b) => format!("{:02X}", b),
+                None => "--".to_owned(),
+            })
+            .collect()
+    };
+
+    let first = hunks[0].start;
+    match symbol_text(&map_file, first) {
+        Some(symbol) => println!("First difference at ROM addr 0x{:X}, {}", first, symbol),
+        None => println!("First difference at ROM addr 0x{:X}", first),
+    }
+
+    for word in words {
+        let location = match symbol_text(&map_file, word) {
+            Some(symbol) => format!("0x{:08X} {}", word, symbol),
+            None => format!("0x{:08X}", word),
+        };
+        println!(
+            "{}: expected {}, got {}",
+            location,
+            word_text(baserom, word),
+            word_text(rom, word)
+        );
+    }
+
+    let symbols: HashSet<&str> = hunks
+        .iter()
+        .flat_map(|h| map_file.data.values(h.clone()))
+        .map(|entry| entry.symbol_name.as_str())
+        .collect();
+    println!(
+        "0x{:X} bytes differ in {} hunks across {} symbols",
+        hunks.iter().map(|h| h.len()).sum::<usize>(),
+        hunks.len(),
+        symbols.len()
+    );
+
+    Ok(true)
+}
+
+fn run_make_patch(args: &MakePatchArgs) -> Result<bool, Error> {
+    let format = args
+        .format
+        .or_else(|| PatchFormat::from_path(&args.output))
+        .unwrap_or(PatchFormat::Ips);
+    let source = load_input(0, &args.source, Endianness::Big)?;
+    let target = load_input(1, &args.target, Endianness::Big)?;
+
+    let ranges = positional_patch_ranges(&source, &target);
+    let patch = create_patch(format, &source.data, &target.data, &ranges)?;
+    std::fs::write(&args.output, patch)
+        .with_context(|| format!("Failed to write {}", args.output.display()))?;
+
+    println!(
+        "Wrote {} patch with {} hunks to {}",
+        format,
+        ranges.len(),
+        args.output.display()
+    );
+    Ok(false)
+}
+
+/// Runs a command without the interface. Exits with 0 on success or if the files match, 1 if they
+/// differ and 2 on errors.
+pub fn run(command: Command) -> ExitCode {
+    let result = match command {
+        Command::Diff(args) => run_diff(&args),
+        Command::FirstDiff(args) => run_first_diff(&args),
+        Command::MakePatch(args) => run_make_patch(&args),
+    };
+
+    match result {
+        Ok(false) => ExitCode::SUCCESS,
+        Ok(true) => ExitCode::from(1),
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(id: usize, bytes: &[u8]) -> DiffInput {
+        DiffInput {
+            id,
+            cur_pos: 0,
+            data: bytes.to_vec().into(),
+            endianness: Endianness::Big,
+        }
+    }
+
+    fn diff(inputs: &[DiffInput; 2]) -> Vec<HunkReport> {
+        let config = DiffConfig {
+            mode: DiffMode::Positional,
+            basis: DeviationBasis::Reference,
+            ignored: Vec::new(),
+        };
+        let diff_state = DiffState::calculate(inputs, &config, &DiffProgress::default()).unwrap();
+        hunk_reports(&diff_state, inputs, None)
+    }
+
+    #[test]
+    fn identical_files_have_no_hunks() {
+        assert!(diff(&[input(0, &[1, 2, 3, 4]), input(1, &[1, 2, 3, 4])]).is_empty());
+    }
+
+    #[test]
+    fn bytes_past_the_end_of_the_other_file_are_a_hunk() {
+        let hunks = diff(&[input(0, &[1, 2, 3, 4]), input(1, &[1, 2, 3, 4, 5, 6, 7, 8])]);
+
+        assert_eq!(hunks.len(), 1);
+        assert_eq!((hunks[0].offset, hunks[0].length), (4, 4));
+        assert_eq!(hunks[0].a, None);
+        assert_eq!(hunks[0].b.as_deref(), Some("05 06 07 08"));
+    }
+
+    #[test]
+    fn a_truncated_file_ends_with_a_hunk() {
+        let hunks = diff(&[input(0, &[1, 2, 3, 4, 5, 6]), input(1, &[1, 9, 3, 4])]);
+
+        assert_eq!(hunks.len(), 2);
+        assert_eq!((hunks[0].offset, hunks[0].length), (1, 1));
+        assert_eq!((hunks[1].offset, hunks[1].length), (4, 2));
+        assert_eq!(hunks[1].a.as_deref(), Some("05 06"));
+        assert_eq!(hunks[1].b, None);
+    }
+}
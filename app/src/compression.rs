@@ -0,0 +1,444 @@
+//! Decompression of the formats game assets are commonly compressed with.
+//!
+//! Yaz0, Yay0 and MIO0 come from Nintendo's N64/GameCube/Wii titles, LZ10 and LZ11 from the GBA
+//! and DS BIOS routines. zlib streams are handed to miniz_oxide.
+
+use std::fmt::Display;
+
+use anyhow::Error;
+use miniz_oxide::{
+    inflate::stream::{inflate, InflateState},
+    DataFormat, MZError, MZFlush, MZStatus,
+};
+
+use crate::bin_file::FileData;
+
+/// Refuse to make anything larger than this, as the sizes come from untrusted headers
+const MAX_DECOMPRESSED_LEN: usize = 256 * 1024 * 1024;
+/// Enough bytes for the header of any of the formats
+const HEADER_LEN: usize = 0x10;
+/// How much of a zlib stream is read at a time, as it doesn't say how long it is
+const ZLIB_READ_LEN: usize = 0x10000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Yaz0,
+    Yay0,
+    Mio0,
+    Lz10,
+    Lz11,
+    Zlib,
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            Self::Yaz0 => "Yaz0",
+            Self::Yay0 => "Yay0",
+            Self::Mio0 => "MIO0",
+            Self::Lz10 => "LZ10",
+            Self::Lz11 => "LZ11",
+            Self::Zlib => "zlib",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+fn be_u32(data: &[u8], offset: usize) -> Option<usize> {
+    Some(u32::from_be_bytes(data.get(offset..offset + 4)?.try_into().unwrap()) as usize)
+}
+
+/// Decompressed size of GBA/DS LZ77 data, and where the compressed stream starts
+fn lz77_header(data: &[u8]) -> Option<(usize, usize)> {
+    let size = data.get(1..4)?;
+    match size[0] as usize | (size[1] as usize) << 8 | (size[2] as usize) << 16 {
+        // Sizes too large for 24 bits follow in another 32 bits
+        0 => Some((
+            u32::from_le_bytes(data.get(4..8)?.try_into().unwrap()) as usize,
+            8,
+        )),
+        size => Some((size, 4)),
+    }
+}
+
+/// The compression `data` starts with, going by its header
+pub fn detect(data: &[u8]) -> Option<Compression> {
+    match data.get(..4)? {
+        b"Yaz0" => return Some(Compression::Yaz0),
+        b"Yay0" => return Some(Compression::Yay0),
+        b"MIO0" => return Some(Compression::Mio0),
+        _ => {}
+    }
+
+    // zlib headers are checksummed and specify deflate with a window of at most 32 KiB
+    let (cmf, flg) = (data[0], data[1]);
+    if cmf & 0x0F == 8 && cmf >> 4 <= 7 && (((cmf as u16) << 8) | flg as u16).is_multiple_of(31) {
+        return Some(Compression::Zlib);
+    }
+
+    // LZ77 only has a type byte, so also require a sensible size
+    let plausible =
+        lz77_header(data).is_some_and(|(size, _)| size > 0 && size <= MAX_DECOMPRESSED_LEN);
+    match data[0] {
+        0x10 if plausible => Some(Compression::Lz10),
+        0x11 if plausible => Some(Compression::Lz11),
+        _ => None,
+    }
+}
+
+/// The decompressed size `header` declares, and the most bytes its format can decompress to per
+/// byte of compressed data (its longest back-reference over the bytes encoding it). `None` for
+/// zlib, which doesn't declare a size.
+fn declared_size(compression: Compression, header: &[u8]) -> Option<(usize, usize)> {
+    let size = match compression {
+        Compression::Yaz0 | Compression::Yay0 | Compression::Mio0 => be_u32(header, 4),
+        Compression::Lz10 | Compression::Lz11 => lz77_header(header).map(|(size, _)| size),
+        Compression::Zlib => None,
+    }?;
+    let (reference_len, encoded_len) = match compression {
+        Compression::Yaz0 | Compression::Yay0 => (0x111, 3),
+        Compression::Mio0 | Compression::Lz10 => (0x12, 2),
+        _ => (0x10110, 4),
+    };
+    Some((size, usize::div_ceil(reference_len, encoded_len)))
+}
+
+/// Decompresses the data at `offset` in `data`, detecting how it's compressed. Only as much of
+/// `data` is read as the compressed data can take up.
+pub fn decompress(data: &FileData, offset: usize) -> Result<(Compression, Vec<u8>), Error> {
+    let available = data.len().saturating_sub(offset);
+    let header = data.read(offset..offset + available.min(HEADER_LEN));
+    let compression = detect(&header)
+        .ok_or_else(|| Error::msg("No Yaz0, Yay0, MIO0, LZ77 or zlib header found"))?;
+
+    let Some((size, max_expansion)) = declared_size(compression, &header) else {
+        return Ok((compression, decompress_zlib(data, offset)?));
+    };
+    let size = checked_size(size)?;
+    if size > available.saturating_mul(max_expansion) {
+        return Err(Error::msg(format!(
+            "Decompressed size 0x{:X} is more than the 0x{:X} bytes left in the file can hold",
+            size, available
+        )));
+    }
+
+    // Compressed data is at its longest when it's all literals, each with its flag bit. Leave
+    // as much room again as the header for padding between Yay0's streams.
+    let max_compressed_len = 2 * HEADER_LEN + size + size.div_ceil(8);
+    let compressed = data.read(offset..offset + available.min(max_compressed_len));
+    let decompressed = match compression {
+        Compression::Yaz0 => decompress_yaz0(&compressed),
+        Compression::Lz10 => decompress_lz10(&compressed),
+        Compression::Lz11 => decompress_lz11(&compressed),
+        _ => decompress_yay0(&compressed, compression),
+    }?;
+
+    Ok((compression, decompressed))
+}
+
+fn truncated() -> Error {
+    Error::msg("Compressed data ends unexpectedly")
+}
+
+fn checked_size(size: usize) -> Result<usize, Error> {
+    match size <= MAX_DECOMPRESSED_LEN {
+        true => Ok(size),
+        false => Err(Error::msg(format!(
+            "Decompressed size 0x{:X} is implausibly large",
+            size
+        ))),
+    }
+}
+
+/// Appends `len` bytes starting `distance` bytes back, which may overlap the bytes appended
+fn copy_back(out: &mut Vec<u8>, distance: usize, len: usize, size: usize) -> Result<(), Error> {
+    let start = out
+        .len()
+        .checked_sub(distance)
+        .ok_or_else(|| Error::msg("Compressed data refers to before its start"))?;
+    for i in start..start + len.min(size - out.len()) {
+        out.push(out[i]);
+    }
+    Ok(())
+}
+
+/// Inflates the zlib stream at `offset` in `data` a piece at a time, stopping where it ends
+fn decompress_zlib(data: &FileData, offset: usize) -> Result<Vec<u8>, Error> {
+    let mut state = InflateState::new_boxed(DataFormat::Zlib);
+    let mut out: Vec<u8> = Vec::new();
+    let mut written = 0;
+
+    for start in (offset..data.len()).step_by(ZLIB_READ_LEN) {
+        let piece = data.read(start..(start + ZLIB_READ_LEN).min(data.len()));
+        let mut input = &piece[..];
+
+        // Inflate all of the piece, making room for more output as it fills up
+        loop {
+            if written == out.len() {
+                if out.len() == MAX_DECOMPRESSED_LEN {
+                    return Err(Error::msg("Decompressed data is implausibly large"));
+                }
+                out.resize(
+                    (out.len() * 2).clamp(ZLIB_READ_LEN, MAX_DECOMPRESSED_LEN),
+                    0,
+                );
+            }
+
+            let result = inflate(&mut state, input, &mut out[written..], MZFlush::None);
+            input = &input[result.bytes_consumed..];
+            written += result.bytes_written;
+            match result.status {
+                Ok(MZStatus::StreamEnd) => {
+                    out.truncate(written);
+                    return Ok(out);
+                }
+                // Out of input or output for now
+                Ok(_) | Err(MZError::Buf) => {}
+                Err(e) => return Err(Error::msg(format!("Invalid zlib data: {:?}", e))),
+            }
+
+            if input.is_empty() && written < out.len() {
+                break;
+            }
+        }
+    }
+
+    Err(truncated())
+}
+
+fn decompress_yaz0(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let size = checked_size(be_u32(data, 4).ok_or_else(truncated)?)?;
+    let mut out = Vec::with_capacity(size);
+    let mut pos = 0x10;
+    let mut byte = || -> Result<u8, Error> {
+        let b = *data.get(pos).ok_or_else(truncated)?;
+        pos += 1;
+        Ok(b)
+    };
+
+    while out.len() < size {
+        let header = byte()?;
+        for bit in (0..8).rev() {
+            if out.len() >= size {
+                break;
+            }
+
+            match header >> bit & 1 {
+                1 => out.push(byte()?),
+                _ => {
+                    let (b1, b2) = (byte()? as usize, byte()? as usize);
+                    let distance = ((b1 & 0xF) << 8 | b2) + 1;
+                    let len = match b1 >> 4 {
+                        0 => byte()? as usize + 0x12,
+                        n => n + 2,
+                    };
+                    copy_back(&mut out, distance, len, size)?;
+                }
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// Yay0 and MIO0 keep the flags, back-references and literals in separate streams
+fn decompress_yay0(data: &[u8], compression: Compression) -> Result<Vec<u8>, Error> {
+    let size = checked_size(be_u32(data, 4).ok_or_else(truncated)?)?;
+    let mut link_pos = be_u32(data, 8).ok_or_else(truncated)?;
+    let mut chunk_pos = be_u32(data, 12).ok_or_else(truncated)?;
+    let mut mask_pos = 0x10;
+
+    let mut out = Vec::with_capacity(size);
+    let mut mask = 0;
+    let mut mask_bits = 0;
+
+    while out.len() < size {
+        if mask_bits == 0 {
+            mask = be_u32(data, mask_pos).ok_or_else(truncated)?;
+            mask_pos += 4;
+            mask_bits = 32;
+        }
+        mask_bits -= 1;
+
+        match mask >> mask_bits & 1 {
+            1 => {
+                out.push(*data.get(chunk_pos).ok_or_else(truncated)?);
+                chunk_pos += 1;
+            }
+            _ => {
+                let link = data.get(link_pos..link_pos + 2).ok_or_else(truncated)?;
+                link_pos += 2;
+                let link = (link[0] as usize) << 8 | link[1] as usize;
+
+                let distance = (link & 0xFFF) + 1;
+                let len = match (compression, link >> 12) {
+                    (Compression::Mio0, n) => n + 3,
+                    (_, 0) => {
+                        let len = *data.get(chunk_pos).ok_or_else(truncated)? as usize + 0x12;
+                        chunk_pos += 1;
+                        len
+                    }
+                    (_, n) => n + 2,
+                };
+                copy_back(&mut out, distance, len, size)?;
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+fn decompress_lz10(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let (size, mut pos) = lz77_header(data).ok_or_else(truncated)?;
+    let size = checked_size(size)?;
+    let mut out = Vec::with_capacity(size);
+    let mut byte = || -> Result<usize, Error> {
+        let b = *data.get(pos).ok_or_else(truncated)?;
+        pos += 1;
+        Ok(b as usize)
+    };
+
+    while out.len() < size {
+        let flags = byte()?;
+        for bit in (0..8).rev() {
+            if out.len() >= size {
+                break;
+            }
+
+            match flags >> bit & 1 {
+                0 => out.push(byte()? as u8),
+                _ => {
+                    let (b1, b2) = (byte()?, byte()?);
+                    let distance = ((b1 & 0xF) << 8 | b2) + 1;
+                    copy_back(&mut out, distance, (b1 >> 4) + 3, size)?;
+                }
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+fn decompress_lz11(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let (size, mut pos) = lz77_header(data).ok_or_else(truncated)?;
+    let size = checked_size(size)?;
+    let mut out = Vec::with_capacity(size);
+    let mut byte = || -> Result<usize, Error> {
+        let b = *data.get(pos).ok_or_else(truncated)?;
+        pos += 1;
+        Ok(b as usize)
+    };
+
+    while out.len() < size {
+        let flags = byte()?;
+        for bit in (0..8).rev() {
+            if out.len() >= size {
+                break;
+            }
+
+            if flags >> bit & 1 == 0 {
+                out.push(byte()? as u8);
+                continue;
+            }
+
+            // The top nibble picks how many bytes the length takes up
+            let b1 = byte()?;
+            let (len, b2) = match b1 >> 4 {
+                0 => {
+                    let b2 = byte()?;
+                    (((b1 & 0xF) << 4 | b2 >> 4) + 0x11, b2)
+                }
+                1 => {
+                    let (b2, b3) = (byte()?, byte()?);
+                    (((b1 & 0xF) << 12 | b2 << 4 | b3 >> 4) + 0x111, b3)
+                }
+                n => (n + 1, b1),
+            };
+            let distance = ((b2 & 0xF) << 8 | byte()?) + 1;
+            copy_back(&mut out, distance, len, size)?;
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What each of the vectors decompresses to: three literals, then a copy of six bytes
+    /// starting three bytes back
+    const DECOMPRESSED: &[u8] = b"abcabcabc";
+
+    fn check_vector(compressed: &[u8], compression: Compression) {
+        assert_eq!(
+            decompress(&compressed.to_vec().into(), 0).unwrap(),
+            (compression, DECOMPRESSED.to_vec())
+        );
+    }
+
+    #[test]
+    fn yaz0_vector() {
+        let mut data = b"Yaz0\0\0\0\x09".to_vec();
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&[0xE0, b'a', b'b', b'c', 0x40, 0x02]);
+        check_vector(&data, Compression::Yaz0);
+    }
+
+    #[test]
+    fn yay0_vector() {
+        // Flags, then the back-reference, then the literals
+        let mut data = b"Yay0\0\0\0\x09\0\0\0\x14\0\0\0\x16".to_vec();
+        data.extend_from_slice(&[0xE0, 0, 0, 0, 0x40, 0x02, b'a', b'b', b'c']);
+        check_vector(&data, Compression::Yay0);
+    }
+
+    #[test]
+    fn mio0_vector() {
+        let mut data = b"MIO0\0\0\0\x09\0\0\0\x14\0\0\0\x16".to_vec();
+        data.extend_from_slice(&[0xE0, 0, 0, 0, 0x30, 0x02, b'a', b'b', b'c']);
+        check_vector(&data, Compression::Mio0);
+    }
+
+    #[test]
+    fn lz10_vector() {
+        check_vector(
+            &[0x10, 0x09, 0, 0, 0x10, b'a', b'b', b'c', 0x30, 0x02],
+            Compression::Lz10,
+        );
+    }
+
+    #[test]
+    fn lz11_vector() {
+        check_vector(
+            &[0x11, 0x09, 0, 0, 0x10, b'a', b'b', b'c', 0x50, 0x02],
+            Compression::Lz11,
+        );
+    }
+
+    #[test]
+    fn zlib_stream_longer_than_a_read() {
+        let original: Vec<u8> = (0..ZLIB_READ_LEN * 3)
+            .map(|i| (i * 31 % 251) as u8)
+            .collect();
+        // Stored uncompressed, so the stream spans several reads
+        let mut data = vec![0xFF; 5];
+        data.extend(miniz_oxide::deflate::compress_to_vec_zlib(&original, 0));
+        // Whatever follows the stream isn't read as part of it
+        data.extend_from_slice(&[0xAA; 0x100]);
+
+        let (compression, decompressed) = decompress(&data.into(), 5).unwrap();
+        assert_eq!(compression, Compression::Zlib);
+        assert_eq!(decompressed, original);
+    }
+
+    #[test]
+    fn size_larger_than_rest_of_file_can_hold() {
+        let mut data = b"Yaz0".to_vec();
+        data.extend_from_slice(&0x100000u32.to_be_bytes());
+        data.extend_from_slice(&[0; 0x18]);
+
+        let err = decompress(&data.into(), 0).err().unwrap();
+        assert!(err.to_string().contains("bytes left in the file"));
+    }
+}
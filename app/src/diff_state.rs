@@ -1,48 +1,941 @@
-use crate::file_view::FileView;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt::Display,
+    ops::Range,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
 
+use bdiff_hex_view::{file_to_view_offset, view_to_file_offset, HexViewRow};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    alignment::{align, layout_gaps},
+    bin_file::{Endianness, FileData},
+    file_view::FileView,
+    mips::is_relocation_diff,
+};
+
+#[derive(Deserialize, Serialize, Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffMode {
+    /// Bytes are compared at the same position
+    #[default]
+    Positional,
+    /// Files are aligned against the first one, detecting inserted and deleted runs of bytes
+    Aligned,
+    /// Bytes are compared at the same position, but 4-byte words that differ only in MIPS
+    /// relocation fields are told apart from real mismatches
+    Mips,
+}
+
+impl Display for DiffMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            Self::Positional => "Positional",
+            Self::Aligned => "Aligned (insertions/deletions)",
+            Self::Mips => "MIPS (relocation-aware)",
+        }
+        .to_string();
+        write!(f, "{}", str)
+    }
+}
+
+#[derive(Deserialize, Serialize, Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviationBasis {
+    /// A file deviates where it differs from the value most files have
+    #[default]
+    Majority,
+    /// A file deviates where it differs from the reference file
+    Reference,
+}
+
+impl Display for DeviationBasis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            Self::Majority => "Majority",
+            Self::Reference => "Reference file",
+        }
+        .to_string();
+        write!(f, "{}", str)
+    }
+}
+
+/// Settings that affect how diffs are calculated
+#[derive(Clone, Debug)]
+pub struct DiffConfig {
+    pub mode: DiffMode,
+    pub basis: DeviationBasis,
+    /// Ranges of reference file offsets to leave out of the diff
+    pub ignored: Vec<Range<usize>>,
+}
+
+/// The parts of a file view that diffing needs, detached from the UI so it can be sent to a worker thread
+#[derive(Clone, Debug)]
+pub struct DiffInput {
+    pub id: usize,
+    pub cur_pos: usize,
+    pub data: FileData,
+    pub endianness: Endianness,
+}
+
+impl From<&FileView> for DiffInput {
+    fn from(fv: &FileView) -> Self {
+        Self {
+            id: fv.id,
+            cur_pos: fv.cur_pos,
+            data: fv.file.data.clone(),
+            endianness: fv.file.endianness,
+        }
+    }
+}
+
+/// Progress of a diff calculation, shared with the thread running it
 #[derive(Debug, Default)]
+pub struct DiffProgress {
+    done: AtomicUsize,
+    total: AtomicUsize,
+    cancelled: AtomicBool,
+}
+
+impl DiffProgress {
+    pub fn fraction(&self) -> f32 {
+        let total = self.total.load(Ordering::Relaxed);
+        match total {
+            0 => 0.0,
+            _ => self.done.load(Ordering::Relaxed) as f32 / total as f32,
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn add_total(&self, amount: usize) {
+        self.total.fetch_add(amount, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, amount: usize) {
+        self.done.fetch_add(amount, Ordering::Relaxed);
+    }
+}
+
+const DIFF_PIECE_SIZE: usize = 0x100000;
+
+/// Walks a file view in increasing global position order
+struct ViewCursor<'a> {
+    data: &'a FileData,
+    gaps: &'a [Range<usize>],
+    start: usize,
+    end: usize,
+    next_gap: usize,
+    gap_bytes_before: usize,
+}
+
+enum ViewSpan<'a> {
+    Outside,
+    Gap,
+    Data(Cow<'a, [u8]>),
+}
+
+impl<'a> ViewCursor<'a> {
+    fn new(input: &'a DiffInput, gaps: &'a [Range<usize>]) -> Self {
+        let gap_len: usize = gaps.iter().map(|g| g.len()).sum();
+        Self {
+            data: &input.data,
+            gaps,
+            start: input.cur_pos,
+            end: input.cur_pos + input.data.len() + gap_len,
+            next_gap: 0,
+            gap_bytes_before: 0,
+        }
+    }
+
+    /// Positions at which what the view shows may change
+    fn boundaries(&self) -> impl Iterator<Item = usize> + '_ {
+        [self.start, self.end].into_iter().chain(
+            self.gaps
+                .iter()
+                .flat_map(|g| [self.start + g.start, self.start + g.end]),
+        )
+    }
+
+    /// What the view shows over `range`, which must not cross any of its boundaries
+    fn span(&mut self, range: Range<usize>) -> ViewSpan<'a> {
+        if range.start < self.start || range.start >= self.end {
+            return ViewSpan::Outside;
+        }
+        let view_pos = range.start - self.start;
+
+        while let Some(gap) = self.gaps.get(self.next_gap) {
+            if gap.end > view_pos {
+                if gap.start <= view_pos {
+                    return ViewSpan::Gap;
+                }
+                break;
+            }
+            self.gap_bytes_before += gap.len();
+            self.next_gap += 1;
+        }
+
+        let offset = view_pos - self.gap_bytes_before;
+        ViewSpan::Data(self.data.read(offset..offset + range.len()))
+    }
+}
+
+/// Appends the ranges in which `a` and `b` differ, offset by `base`
+fn push_mismatches(hunks: &mut Vec<Range<usize>>, base: usize, a: &[u8], b: &[u8]) {
+    const CHUNK_SIZE: usize = 256;
+
+    for (chunk_idx, (a_chunk, b_chunk)) in
+        a.chunks(CHUNK_SIZE).zip(b.chunks(CHUNK_SIZE)).enumerate()
+    {
+        if a_chunk == b_chunk {
+            continue;
+        }
+
+        let chunk_base = base + chunk_idx * CHUNK_SIZE;
+        for (i, _) in a_chunk
+            .iter()
+            .zip(b_chunk)
+            .enumerate()
+            .filter(|(_, (x, y))| x != y)
+        {
+            let pos = chunk_base + i;
+            match hunks.last_mut() {
+                Some(last) if last.end == pos => last.end += 1,
+                _ => hunks.push(pos..pos + 1),
+            }
+        }
+    }
+}
+
+/// Sorts ranges and merges those that overlap or touch
+pub fn merge_ranges(mut ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    ranges.sort_by_key(|r| r.start);
+
+    let mut ret: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for r in ranges {
+        match ret.last_mut() {
+            Some(last) if r.start <= last.end => last.end = last.end.max(r.end),
+            _ => ret.push(r),
+        }
+    }
+    ret
+}
+
+/// Appends `pos` to sorted `ranges`, extending the last range if it ends right before `pos`
+fn push_pos(ranges: &mut Vec<Range<usize>>, pos: usize) {
+    match ranges.last_mut() {
+        Some(last) if last.end == pos => last.end += 1,
+        _ => ranges.push(pos..pos + 1),
+    }
+}
+
+/// Removes the parts of sorted `ranges` that lie within sorted, non-overlapping `excluded`
+fn subtract_ranges(ranges: Vec<Range<usize>>, excluded: &[Range<usize>]) -> Vec<Range<usize>> {
+    if excluded.is_empty() {
+        return ranges;
+    }
+
+    let mut ret = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        let mut start = range.start;
+        let first = excluded.partition_point(|e| e.end <= range.start);
+        for e in excluded[first..].iter().take_while(|e| e.start < range.end) {
+            if e.start > start {
+                ret.push(start..e.start);
+            }
+            start = start.max(e.end);
+        }
+        if start < range.end {
+            ret.push(start..range.end);
+        }
+    }
+    ret
+}
+
+/// Replaces the parts of sorted `ranges` within `window` with `new`, which must lie within `window`
+fn splice_ranges(ranges: &mut Vec<Range<usize>>, window: &Range<usize>, new: Vec<Range<usize>>) {
+    // Ranges touching the window may extend past it; rebuild them around it
+    let first = ranges.partition_point(|r| r.end < window.start);
+    let last = ranges.partition_point(|r| r.start <= window.end);
+
+    let mut replacement: Vec<Range<usize>> = Vec::with_capacity(new.len() + 2);
+    if let Some(r) = ranges
+        .get(first)
+        .filter(|r| r.start < window.start && first < last)
+    {
+        replacement.push(r.start..window.start);
+    }
+    replacement.extend(new);
+    if let Some(r) = ranges[first..last].last().filter(|r| r.end > window.end) {
+        replacement.push(window.end..r.end);
+    }
+    ranges.splice(first..last, merge_ranges(replacement));
+}
+
+/// Records which of the files showing `values` at `pos` deviate from the basis value
+fn push_deviations(
+    deviations: &mut [Vec<Range<usize>>],
+    present: &[usize],
+    values: &[Option<u8>],
+    pos: usize,
+    basis: DeviationBasis,
+) {
+    let basis_value = match basis {
+        // The reference is the first input, which may not reach this far
+        DeviationBasis::Reference => match present.first() {
+            Some(0) => values[0],
+            _ => None,
+        },
+        // The most common value, the earliest file winning ties
+        DeviationBasis::Majority => {
+            let count = |v: &Option<u8>| values.iter().filter(|&w| w == v).count();
+            let mut best = values[0];
+            let mut best_count = count(&best);
+            for v in values[1..].iter() {
+                let c = count(v);
+                if c > best_count {
+                    best = *v;
+                    best_count = c;
+                }
+            }
+            best
+        }
+    };
+
+    // Without a byte of the reference to compare with, every other file deviates from it
+    let all_deviate = basis == DeviationBasis::Reference && basis_value.is_none();
+    for (&input_idx, value) in present.iter().zip(values) {
+        if (all_deviate && input_idx != 0) || *value != basis_value {
+            push_pos(&mut deviations[input_idx], pos);
+        }
+    }
+}
+
+/// The 4-byte word of `input` at global position `pos`, if it has all of its bytes
+fn word_at(input: &DiffInput, pos: usize) -> Option<u32> {
+    let offset = pos.checked_sub(input.cur_pos)?;
+    let end = offset
+        .checked_add(4)
+        .filter(|&end| end <= input.data.len())?;
+    let bytes: [u8; 4] = input.data.read(offset..end)[..].try_into().ok()?;
+    Some(match input.endianness {
+        Endianness::Little => u32::from_le_bytes(bytes),
+        Endianness::Big => u32::from_be_bytes(bytes),
+    })
+}
+
+/// Whether the files differ in the word at `pos` only where MIPS relocations go
+fn is_relocation_word(inputs: &[DiffInput], pos: usize) -> bool {
+    let Some(reference) = word_at(&inputs[0], pos) else {
+        return false;
+    };
+
+    inputs[1..].iter().all(|input| {
+        let Some(offset) = pos.checked_sub(input.cur_pos) else {
+            // The file starts after this word
+            return pos + 4 <= input.cur_pos;
+        };
+        match word_at(input, pos) {
+            Some(word) => word == reference || is_relocation_diff(reference, word),
+            // Fine if the file has ended before this word, not if it ends within it
+            None => offset >= input.data.len(),
+        }
+    })
+}
+
+/// Splits `hunks` into real mismatches and relocation-only differences, going word by word
+/// with words aligned to the reference file
+fn split_relocations(
+    inputs: &[DiffInput],
+    hunks: Vec<Range<usize>>,
+) -> (Vec<Range<usize>>, Vec<Range<usize>>) {
+    let base = inputs[0].cur_pos;
+    let mut mismatches = Vec::new();
+    let mut relocations = Vec::new();
+
+    for hunk in hunks {
+        let mut pos = hunk.start;
+        while pos < hunk.end {
+            let (word_start, word_end) = match pos >= base {
+                true => {
+                    let word_start = pos - (pos - base) % 4;
+                    (Some(word_start), word_start + 4)
+                }
+                false => (None, base),
+            };
+            let part = pos..word_end.min(hunk.end);
+
+            match word_start.is_some_and(|w| is_relocation_word(inputs, w)) {
+                true => relocations.push(part.clone()),
+                false => mismatches.push(part.clone()),
+            }
+            pos = part.end;
+        }
+    }
+
+    (merge_ranges(mismatches), merge_ranges(relocations))
+}
+
+/// Hunks and per-input deviations found within a window
+struct WindowDiff {
+    hunks: Vec<Range<usize>>,
+    relocations: Vec<Range<usize>>,
+    deviations: Vec<Vec<Range<usize>>>,
+}
+
+#[derive(Clone, Debug, Default)]
 pub struct DiffState {
-    pub diffs: Vec<bool>,
+    /// Sorted, non-overlapping ranges of global positions in which the files differ
+    hunks: Vec<Range<usize>>,
+    /// Ranges (relative to the file's position) in which a file view shows no bytes, by file view id
+    gaps: HashMap<usize, Vec<Range<usize>>>,
+    /// Sorted ranges of global positions in which a file deviates from the basis, by file view id
+    deviations: HashMap<usize, Vec<Range<usize>>>,
+    /// The file the others were compared against
+    reference: Option<DiffInput>,
+    /// Sorted, non-overlapping ranges of global positions that are left out of the diff
+    ignored: Vec<Range<usize>>,
+    /// Sorted, non-overlapping ranges of global positions that differ only in MIPS relocations
+    relocations: Vec<Range<usize>>,
 }
 
 impl DiffState {
-    pub fn get_next_diff(&self, start: usize) -> Option<usize> {
-        for (i, diff) in self.diffs.iter().enumerate().skip(start) {
-            if *diff {
-                return Some(i);
+    pub fn hunks(&self) -> &[Range<usize>] {
+        &self.hunks
+    }
+
+    /// Total number of differing bytes
+    pub fn num_diff_bytes(&self) -> usize {
+        self.hunks.iter().map(|h| h.len()).sum()
+    }
+
+    /// First hunk that starts at or after `pos`
+    pub fn next_hunk(&self, pos: usize) -> Option<&Range<usize>> {
+        let idx = self.hunks.partition_point(|h| h.start < pos);
+        self.hunks.get(idx)
+    }
+
+    /// Whether a hunk lies within `context_rows` rows of the row starting at `row_start`
+    fn near_hunk(&self, row_start: usize, bytes_per_row: usize, context_rows: usize) -> bool {
+        let lo = row_start.saturating_sub(context_rows * bytes_per_row);
+        let hi = row_start + (context_rows + 1) * bytes_per_row;
+        let idx = self.hunks.partition_point(|h| h.end <= lo);
+        self.hunks.get(idx).is_some_and(|h| h.start < hi)
+    }
+
+    /// Lays out `num_rows` rows from `start`, folding runs of rows that are further than
+    /// `context_rows` rows away from any hunk into a single row. Rows are aligned to `start`.
+    pub fn collapsed_rows(
+        &self,
+        start: usize,
+        end: usize,
+        bytes_per_row: usize,
+        num_rows: usize,
+        context_rows: usize,
+    ) -> Vec<HexViewRow> {
+        let mut rows = Vec::with_capacity(num_rows);
+        let mut pos = start;
+
+        while rows.len() < num_rows {
+            if pos >= end || self.near_hunk(pos, bytes_per_row, context_rows) {
+                rows.push(HexViewRow::Bytes(pos));
+                pos += bytes_per_row;
+                continue;
+            }
+
+            // Fold everything up to the context of the next hunk
+            let fold_end = match self.next_hunk(pos) {
+                Some(hunk) => {
+                    let hunk_row = pos + (hunk.start - pos) / bytes_per_row * bytes_per_row;
+                    hunk_row.saturating_sub(context_rows * bytes_per_row)
+                }
+                None => pos + (end - pos).div_ceil(bytes_per_row) * bytes_per_row,
             }
+            .max(pos + bytes_per_row);
+
+            rows.push(HexViewRow::Folded(pos..fold_end.min(end)));
+            pos = fold_end;
         }
 
-        None
+        rows
+    }
+
+    /// First row of the fold containing the row starting at `row_start`, if it is folded
+    pub fn fold_start(
+        &self,
+        row_start: usize,
+        bytes_per_row: usize,
+        context_rows: usize,
+    ) -> Option<usize> {
+        if self.near_hunk(row_start, bytes_per_row, context_rows) {
+            return None;
+        }
+
+        let fold_start = match self.prev_hunk(row_start) {
+            Some(hunk) => {
+                // Rows back to the one containing the last byte of the hunk, aligned to
+                // `row_start`. That row may start before 0, but the context after it doesn't.
+                let rows_back = (row_start - (hunk.end - 1)).div_ceil(bytes_per_row);
+                row_start + (context_rows + 1) * bytes_per_row - rows_back * bytes_per_row
+            }
+            None => row_start % bytes_per_row,
+        };
+        Some(fold_start.min(row_start))
+    }
+
+    /// Last hunk that starts before `pos`
+    pub fn prev_hunk(&self, pos: usize) -> Option<&Range<usize>> {
+        let idx = self.hunks.partition_point(|h| h.start < pos);
+        idx.checked_sub(1).map(|idx| &self.hunks[idx])
+    }
+
+    pub fn gaps(&self, fv_id: usize) -> Option<&[Range<usize>]> {
+        self.gaps.get(&fv_id).map(|g| &g[..])
+    }
+
+    pub fn has_gaps(&self) -> bool {
+        !self.gaps.is_empty()
+    }
+
+    /// Positions at which a file view's bytes deviate from the others
+    pub fn deviations(&self, fv_id: usize) -> &[Range<usize>] {
+        self.deviations.get(&fv_id).map_or(&[], |d| &d[..])
+    }
+
+    pub fn ignored(&self) -> &[Range<usize>] {
+        &self.ignored
+    }
+
+    pub fn relocations(&self) -> &[Range<usize>] {
+        &self.relocations
+    }
+
+    /// The file view the others were compared against
+    pub fn reference_id(&self) -> Option<usize> {
+        self.reference.as_ref().map(|r| r.id)
+    }
+
+    /// Offset into the reference file of the byte it shows at global position `pos`
+    pub fn reference_offset(&self, pos: usize) -> Option<usize> {
+        let reference = self.reference.as_ref()?;
+        let offset =
+            view_to_file_offset(pos.checked_sub(reference.cur_pos)?, self.gaps(reference.id))?;
+        (offset < reference.data.len()).then_some(offset)
+    }
+
+    /// Byte of the reference file at global position `pos`
+    pub fn reference_byte(&self, pos: usize) -> Option<u8> {
+        let offset = self.reference_offset(pos)?;
+        self.reference.as_ref().and_then(|r| r.data.get(offset))
     }
 
-    pub fn recalculate(&mut self, file_views: &[FileView]) {
-        if file_views.len() < 2 {
-            return;
+    /// Ranges of offsets into `target` in which it differs from the reference, to make a patch
+    /// from. Returns `None` unless both were compared byte for byte at the same position.
+    pub fn patch_ranges(&self, target: &DiffInput) -> Option<Vec<Range<usize>>> {
+        let reference = self.reference.as_ref()?;
+        if reference.id == target.id
+            || reference.cur_pos != target.cur_pos
+            || !self.gaps.is_empty()
+            || !self.ignored.is_empty()
+        {
+            return None;
         }
 
-        let max_size = file_views
+        let pos = target.cur_pos;
+        let mut ranges: Vec<Range<usize>> = self
+            .hunks
             .iter()
-            .map(|fv| fv.file.data.len() + fv.cur_pos)
-            .max()
-            .unwrap();
+            .chain(self.relocations.iter())
+            .map(|r| r.start.saturating_sub(pos)..r.end.saturating_sub(pos).min(target.data.len()))
+            .filter(|r| !r.is_empty())
+            .collect();
+
+        // Bytes past the end of the reference aren't compared, but must be in the patch
+        if target.data.len() > reference.data.len() {
+            ranges.push(reference.data.len()..target.data.len());
+        }
+
+        Some(merge_ranges(ranges))
+    }
+
+    /// Length of a file view once its gaps are taken into account
+    pub fn display_len(&self, fv: &FileView) -> usize {
+        self.input_display_len(fv.id, &fv.file.data)
+    }
+
+    fn input_display_len(&self, id: usize, data: &FileData) -> usize {
+        let gap_len: usize = self
+            .gaps(id)
+            .map_or(0, |gaps| gaps.iter().map(|g| g.len()).sum());
+        data.len() + gap_len
+    }
+
+    /// Global positions from where the first of `inputs` to end runs out of bytes to where the
+    /// last one does. Bytes there aren't compared, as not all of the files have them. Returns
+    /// `None` if the files all end together.
+    pub fn length_mismatch(&self, inputs: &[DiffInput]) -> Option<Range<usize>> {
+        let ends = inputs
+            .iter()
+            .map(|i| i.cur_pos + self.input_display_len(i.id, &i.data));
+        let start = ends.clone().min()?;
+        let end = ends.max()?;
+        (start < end).then_some(start..end)
+    }
+
+    /// Diffs `inputs` from scratch, the first one being the reference.
+    /// Returns `None` if the calculation was cancelled.
+    pub fn calculate(
+        inputs: &[DiffInput],
+        config: &DiffConfig,
+        progress: &DiffProgress,
+    ) -> Option<Self> {
+        let mut ret = Self::default();
+
+        if inputs.len() < 2 {
+            return Some(ret);
+        }
+
+        ret.reference = Some(inputs[0].clone());
 
-        self.diffs = Vec::with_capacity(max_size);
+        if config.mode == DiffMode::Aligned {
+            // Aligning looks all over the files, so it reads them in whole
+            let reference = inputs[0].data.to_vec();
+            progress.add_total(inputs[1..].iter().map(|i| i.data.len()).sum());
+
+            let mut alignments = Vec::with_capacity(inputs.len() - 1);
+            for input in inputs[1..].iter() {
+                if progress.is_cancelled() {
+                    return None;
+                }
+                alignments.push(align(&reference, &input.data.to_vec()));
+                progress.advance(input.data.len());
+            }
+
+            for (input, gaps) in inputs.iter().zip(layout_gaps(reference.len(), &alignments)) {
+                if !gaps.is_empty() {
+                    ret.gaps.insert(input.id, gaps);
+                }
+            }
+        }
 
-        for i in 0..max_size {
-            let comps: Vec<u8> = file_views
+        let reference = &inputs[0];
+        let reference_gaps = ret.gaps(reference.id);
+        ret.ignored = merge_ranges(
+            config
+                .ignored
                 .iter()
-                .filter(|fv| i >= fv.cur_pos && i < fv.cur_pos + fv.file.data.len())
-                .map(|fv| fv.file.data[(i as isize - fv.cur_pos as isize) as usize])
-                .collect();
+                .filter(|r| !r.is_empty())
+                .map(|r| {
+                    let start = file_to_view_offset(r.start, reference_gaps);
+                    let end = file_to_view_offset(r.end - 1, reference_gaps) + 1;
+                    start + reference.cur_pos..end + reference.cur_pos
+                })
+                .collect(),
+        );
+
+        let diff = ret.diff_window(inputs, 0..usize::MAX, config, progress)?;
+        ret.hunks = diff.hunks;
+        ret.relocations = diff.relocations;
+        for (input, deviations) in inputs.iter().zip(diff.deviations) {
+            ret.deviations.insert(input.id, deviations);
+        }
+        Some(ret)
+    }
+
+    /// Re-diffs only the `changed` global ranges of `inputs`, keeping the hunks found elsewhere.
+    /// Only valid when the file positions and lengths are the same as when `self` was calculated.
+    /// Returns `None` if the calculation was cancelled.
+    pub fn recalculate_ranges(
+        &self,
+        inputs: &[DiffInput],
+        changed: &[Range<usize>],
+        config: &DiffConfig,
+        progress: &DiffProgress,
+    ) -> Option<Self> {
+        let mut ret = self.clone();
+        ret.reference = inputs.first().cloned();
+
+        for range in merge_ranges(changed.to_vec()) {
+            let diff = self.diff_window(inputs, range.clone(), config, progress)?;
+
+            splice_ranges(&mut ret.hunks, &range, diff.hunks);
+            splice_ranges(&mut ret.relocations, &range, diff.relocations);
+            for (input, deviations) in inputs.iter().zip(diff.deviations) {
+                splice_ranges(
+                    ret.deviations.entry(input.id).or_default(),
+                    &range,
+                    deviations,
+                );
+            }
+        }
+
+        Some(ret)
+    }
+
+    /// Diffs `inputs` within `window`, using the gaps in `self`
+    fn diff_window(
+        &self,
+        inputs: &[DiffInput],
+        window: Range<usize>,
+        config: &DiffConfig,
+        progress: &DiffProgress,
+    ) -> Option<WindowDiff> {
+        let mut cursors: Vec<ViewCursor> = inputs
+            .iter()
+            .map(|i| ViewCursor::new(i, self.gaps.get(&i.id).map_or(&[], |g| &g[..])))
+            .collect();
+
+        // Split the window into segments within which no view changes between bytes, gaps and
+        // nothing, so each segment can be compared a slice at a time
+        let end = window
+            .end
+            .min(cursors.iter().map(|c| c.end).max().unwrap_or(0));
+        let start = window.start.min(end);
+        let mut boundaries: Vec<usize> = cursors
+            .iter()
+            .flat_map(|c| c.boundaries())
+            .filter(|&b| b > start && b < end)
+            .chain([start, end])
+            .collect();
+        boundaries.sort_unstable();
+        boundaries.dedup();
+
+        progress.add_total(end - start);
+
+        let mut ret = WindowDiff {
+            hunks: Vec::new(),
+            relocations: Vec::new(),
+            deviations: vec![Vec::new(); inputs.len()],
+        };
+        let mut present: Vec<usize> = Vec::with_capacity(cursors.len());
+        let mut spans: Vec<ViewSpan> = Vec::with_capacity(cursors.len());
+        let mut values: Vec<Option<u8>> = Vec::with_capacity(cursors.len());
+
+        for segment in boundaries.windows(2).map(|w| w[0]..w[1]) {
+            // Compare large segments piecewise so cancellation and progress stay responsive
+            for piece_start in segment.clone().step_by(DIFF_PIECE_SIZE) {
+                if progress.is_cancelled() {
+                    return None;
+                }
+
+                let piece = piece_start..(piece_start + DIFF_PIECE_SIZE).min(segment.end);
+                progress.advance(piece.len());
+
+                present.clear();
+                spans.clear();
+                for (idx, cursor) in cursors.iter_mut().enumerate() {
+                    match cursor.span(piece.clone()) {
+                        ViewSpan::Outside => {}
+                        span => {
+                            present.push(idx);
+                            spans.push(span);
+                        }
+                    }
+                }
+
+                if spans.len() < 2 {
+                    continue;
+                }
+
+                let piece_hunks = match spans.iter().any(|s| matches!(s, ViewSpan::Gap)) {
+                    // A gap next to actual bytes is an insertion or deletion
+                    true => vec![piece.clone()],
+                    false => {
+                        let data: Vec<&[u8]> = spans
+                            .iter()
+                            .filter_map(|s| match s {
+                                ViewSpan::Data(data) => Some(&data[..]),
+                                _ => None,
+                            })
+                            .collect();
+
+                        let mut piece_hunks = Vec::new();
+                        for other in data[1..].iter() {
+                            push_mismatches(&mut piece_hunks, piece.start, data[0], other);
+                        }
+                        merge_ranges(piece_hunks)
+                    }
+                };
+                let piece_hunks = subtract_ranges(piece_hunks, &self.ignored);
 
-            if comps.len() < 2 {
-                self.diffs.push(false);
-            } else {
-                let first = comps[0];
-                let same = comps.iter().all(|&c| c == first);
-                self.diffs.push(!same);
+                for pos in piece_hunks.iter().flat_map(|h| h.clone()) {
+                    let i = pos - piece.start;
+                    values.clear();
+                    values.extend(spans.iter().map(|s| match s {
+                        ViewSpan::Data(data) => Some(data[i]),
+                        _ => None,
+                    }));
+                    push_deviations(&mut ret.deviations, &present, &values, pos, config.basis);
+                }
+
+                ret.hunks.extend(piece_hunks);
+            }
+        }
+
+        ret.hunks = merge_ranges(ret.hunks);
+
+        if config.mode == DiffMode::Mips {
+            (ret.hunks, ret.relocations) = split_relocations(inputs, ret.hunks);
+            for deviations in ret.deviations.iter_mut() {
+                *deviations = subtract_ranges(std::mem::take(deviations), &ret.relocations);
+            }
+        }
+
+        Some(ret)
+    }
+}
+
+/// Ranges of `new` that differ from `old`, plus whatever was added or removed at the end
+pub fn changed_ranges(old: &FileData, new: &FileData) -> Vec<Range<usize>> {
+    let (shorter, longer) = (old.len().min(new.len()), old.len().max(new.len()));
+    let (old_parts, new_parts) = (old.parts(), new.parts());
+
+    // Walk both a part at a time, only comparing the bytes they don't read from the same place,
+    // so an edit costs as much as the bytes it changed
+    let mut ret = Vec::new();
+    let (mut old_idx, mut new_idx) = (0, 0);
+    let (mut old_start, mut new_start) = (0, 0);
+    let mut pos = 0;
+    while pos < shorter {
+        let (old_data, old_range) = &old_parts[old_idx];
+        let (new_data, new_range) = &new_parts[new_idx];
+        let old_end = old_start + old_range.len();
+        let new_end = new_start + new_range.len();
+        let end = old_end.min(new_end).min(shorter);
+
+        let old_offset = old_range.start + pos - old_start;
+        let new_offset = new_range.start + pos - new_start;
+        if !FileData::ptr_eq(old_data, new_data) || old_offset != new_offset {
+            for start in (pos..end).step_by(DIFF_PIECE_SIZE) {
+                let len = (end - start).min(DIFF_PIECE_SIZE);
+                let (a, b) = (old_offset + start - pos, new_offset + start - pos);
+                push_mismatches(
+                    &mut ret,
+                    start,
+                    &old_data.read(a..a + len),
+                    &new_data.read(b..b + len),
+                );
             }
         }
+
+        pos = end;
+        if pos == old_end {
+            old_idx += 1;
+            old_start = old_end;
+        }
+        if pos == new_end {
+            new_idx += 1;
+            new_start = new_end;
+        }
+    }
+
+    if shorter < longer {
+        ret.push(shorter..longer);
+    }
+    merge_ranges(ret)
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+
+    fn input(id: usize, bytes: Vec<u8>) -> DiffInput {
+        DiffInput {
+            id,
+            cur_pos: 0,
+            data: bytes.into(),
+            endianness: Endianness::Big,
+        }
+    }
+
+    fn config(basis: DeviationBasis) -> DiffConfig {
+        DiffConfig {
+            mode: DiffMode::Positional,
+            basis,
+            ignored: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn recalculating_changed_ranges_matches_a_full_diff() {
+        let a: Vec<u8> = (0..0x1000).map(|i| (i * 7) as u8).collect();
+        let mut b = a.clone();
+        b[0x10] ^= 1;
+        b[0x800..0x810].fill(0);
+        let mut c = a.clone();
+        c[0x20] ^= 1;
+
+        let inputs = vec![input(0, a.clone()), input(1, b.clone()), input(2, c)];
+        let config = config(DeviationBasis::Majority);
+        let progress = DiffProgress::default();
+        let base = DiffState::calculate(&inputs, &config, &progress).unwrap();
+
+        // Put back some of the bytes that differed, and change others
+        let mut edited = b;
+        edited[0x10] ^= 1;
+        edited[0x805] = a[0x805];
+        edited[0x20] ^= 1;
+        edited[0xFFF] ^= 0xFF;
+        let mut new_inputs = inputs.clone();
+        new_inputs[1].data = edited.into();
+
+        let changed = changed_ranges(&inputs[1].data, &new_inputs[1].data);
+        let incremental = base
+            .recalculate_ranges(&new_inputs, &changed, &config, &progress)
+            .unwrap();
+        let full = DiffState::calculate(&new_inputs, &config, &progress).unwrap();
+
+        assert_eq!(incremental.hunks(), full.hunks());
+        for id in 0..3 {
+            assert_eq!(incremental.deviations(id), full.deviations(id));
+        }
+    }
+
+    #[test]
+    fn reference_deviations_before_reference_starts() {
+        // The reference has the highest id and starts after the others
+        let mut reference = input(2, vec![0x10, 0x11]);
+        reference.cur_pos = 2;
+        let inputs = vec![
+            reference,
+            input(0, vec![0x00, 0x01, 0x10, 0x11]),
+            input(1, vec![0x00, 0x02, 0x10, 0x11]),
+        ];
+
+        let progress = DiffProgress::default();
+        let diff_state =
+            DiffState::calculate(&inputs, &config(DeviationBasis::Reference), &progress).unwrap();
+
+        assert_eq!(diff_state.hunks(), &[1..2]);
+        assert_eq!(diff_state.deviations(0), &[1..2]);
+        assert_eq!(diff_state.deviations(1), &[1..2]);
+        assert!(diff_state.deviations(2).is_empty());
+    }
+
+    #[test]
+    fn reference_deviations_past_end_of_shorter_reference() {
+        let inputs = vec![
+            input(0, vec![0x00, 0x01]),
+            input(1, vec![0x00, 0x01, 0x02]),
+            input(2, vec![0x00, 0x01, 0x03]),
+        ];
+
+        let progress = DiffProgress::default();
+        let diff_state =
+            DiffState::calculate(&inputs, &config(DeviationBasis::Reference), &progress).unwrap();
+
+        assert_eq!(diff_state.hunks(), &[2..3]);
+        assert!(diff_state.deviations(0).is_empty());
+        assert_eq!(diff_state.deviations(1), &[2..3]);
+        assert_eq!(diff_state.deviations(2), &[2..3]);
     }
 }
@@ -0,0 +1,64 @@
+use std::{
+    sync::{
+        mpsc::{self, Receiver, TryRecvError},
+        Arc,
+    },
+    thread,
+};
+
+use crate::diff_state::{DiffProgress, DiffState};
+
+pub enum DiffTaskStatus<T = DiffState> {
+    Running,
+    Finished(Box<T>),
+    /// The task was cancelled or its thread died
+    Aborted,
+}
+
+/// A diff calculation, or other work comparing files, running on a worker thread
+pub struct DiffTask<T = DiffState> {
+    progress: Arc<DiffProgress>,
+    receiver: Receiver<Option<T>>,
+}
+
+impl<T: Send + 'static> DiffTask<T> {
+    pub fn spawn<F>(calculate: F) -> Self
+    where
+        F: FnOnce(&DiffProgress) -> Option<T> + Send + 'static,
+    {
+        let progress = Arc::new(DiffProgress::default());
+        let (sender, receiver) = mpsc::channel();
+
+        let thread_progress = progress.clone();
+        thread::spawn(move || {
+            let _ = sender.send(calculate(&thread_progress));
+        });
+
+        Self { progress, receiver }
+    }
+}
+
+impl<T> DiffTask<T> {
+    pub fn progress(&self) -> f32 {
+        self.progress.fraction()
+    }
+
+    pub fn cancel(&self) {
+        self.progress.cancel();
+    }
+
+    pub fn poll(&self) -> DiffTaskStatus<T> {
+        match self.receiver.try_recv() {
+            Ok(Some(state)) => DiffTaskStatus::Finished(Box::new(state)),
+            Ok(None) | Err(TryRecvError::Disconnected) => DiffTaskStatus::Aborted,
+            Err(TryRecvError::Empty) => DiffTaskStatus::Running,
+        }
+    }
+}
+
+impl<T> Drop for DiffTask<T> {
+    fn drop(&mut self) {
+        // Nobody is waiting for the result anymore
+        self.cancel();
+    }
+}
@@ -0,0 +1,496 @@
+//! Changes made to a file's bytes in the editor, on top of what was loaded from disk.
+//!
+//! The edited contents are kept as a piece table: a list of pieces, each a range of either the
+//! contents the edits started from or of bytes added by them. Inserting and deleting bytes only
+//! splits and splices pieces, and undoing a step puts back the pieces it replaced, so no step
+//! copies the file's bytes or moves those after the edit. The contents the views and diffs see
+//! are read through the pieces. Only short pieces of added bytes next to each other, like those
+//! of bytes typed one at a time, are copied into one, so they don't pile up.
+
+use std::ops::Range;
+
+use crate::{bin_file::FileData, diff_state::merge_ranges};
+
+/// Neighbouring pieces of added bytes at most this long together are copied into a single piece
+const MAX_COPIED_LEN: usize = 0x100;
+
+/// A range of the contents the edits started from or of bytes added by them
+#[derive(Clone, Debug)]
+struct Piece {
+    data: FileData,
+    range: Range<usize>,
+}
+
+impl Piece {
+    fn new(bytes: Vec<u8>) -> Self {
+        Self {
+            range: 0..bytes.len(),
+            data: bytes.into(),
+        }
+    }
+
+    /// Whether `next` starts where this piece ends in the same contents
+    fn continues_into(&self, next: &Piece) -> bool {
+        FileData::ptr_eq(&self.data, &next.data) && self.range.end == next.range.start
+    }
+}
+
+fn pieces_len(pieces: &[Piece]) -> usize {
+    pieces.iter().map(|p| p.range.len()).sum()
+}
+
+#[derive(Debug, Default)]
+struct PieceTable {
+    original: FileData,
+    pieces: Vec<Piece>,
+}
+
+impl PieceTable {
+    fn new(original: FileData) -> Self {
+        let pieces = match original.is_empty() {
+            true => Vec::new(),
+            false => vec![Piece {
+                range: 0..original.len(),
+                data: original.clone(),
+            }],
+        };
+        Self { original, pieces }
+    }
+
+    fn is_added(&self, piece: &Piece) -> bool {
+        !FileData::ptr_eq(&piece.data, &self.original)
+    }
+
+    fn len(&self) -> usize {
+        pieces_len(&self.pieces)
+    }
+
+    /// Splits the piece containing `offset` so that a piece starts there, returning its index
+    fn split_at(&mut self, offset: usize) -> usize {
+        let mut start = 0;
+        for idx in 0..self.pieces.len() {
+            let piece = &self.pieces[idx];
+            if offset == start {
+                return idx;
+            }
+            if offset < start + piece.range.len() {
+                let split = piece.range.start + offset - start;
+                let tail = Piece {
+                    data: piece.data.clone(),
+                    range: split..piece.range.end,
+                };
+                self.pieces[idx].range.end = split;
+                self.pieces.insert(idx + 1, tail);
+                return idx + 1;
+            }
+            start += piece.range.len();
+        }
+        self.pieces.len()
+    }
+
+    /// Replaces the pieces covering `range` with `new`, returning the pieces replaced
+    fn replace(&mut self, range: Range<usize>, new: Vec<Piece>) -> Vec<Piece> {
+        let first = self.split_at(range.start);
+        let last = self.split_at(range.end);
+        let new_len = new.len();
+        let old: Vec<Piece> = self.pieces.splice(first..last, new).collect();
+
+        // Join the pieces around the new ones where they continue each other, as pieces split
+        // by an edit do once it's undone, or are added bytes short enough to copy
+        let mut idx = (first + new_len).min(self.pieces.len().saturating_sub(1));
+        while idx > first.saturating_sub(1) {
+            let (prev, next) = (&self.pieces[idx - 1], &self.pieces[idx]);
+            if prev.continues_into(next) {
+                self.pieces[idx - 1].range.end = next.range.end;
+                self.pieces.remove(idx);
+            } else if self.is_added(prev)
+                && self.is_added(next)
+                && prev.range.len() + next.range.len() <= MAX_COPIED_LEN
+            {
+                let mut bytes = prev.data.read(prev.range.clone()).into_owned();
+                bytes.extend_from_slice(&next.data.read(next.range.clone()));
+                self.pieces[idx - 1] = Piece::new(bytes);
+                self.pieces.remove(idx);
+            }
+            idx -= 1;
+        }
+        old
+    }
+
+    /// Whether the bytes in `range` are `bytes`
+    fn matches(&self, range: Range<usize>, bytes: &[u8]) -> bool {
+        if range.len() != bytes.len() {
+            return false;
+        }
+
+        let mut start = 0;
+        let mut compared = 0;
+        for piece in self.pieces.iter() {
+            let end = start + piece.range.len();
+            if end > range.start && start < range.end {
+                let from = range.start.max(start) - start + piece.range.start;
+                let to = range.end.min(end) - start + piece.range.start;
+                if *piece.data.read(from..to) != bytes[compared..compared + to - from] {
+                    return false;
+                }
+                compared += to - from;
+            }
+            start = end;
+        }
+        true
+    }
+
+    /// The contents, read through the pieces
+    fn contents(&self) -> FileData {
+        FileData::join(
+            self.pieces
+                .iter()
+                .map(|p| (p.data.clone(), p.range.clone())),
+        )
+    }
+}
+
+/// Bytes at `offset` replaced with a number of bytes that may differ, which moves those after them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Resize {
+    pub offset: usize,
+    pub old_len: usize,
+    pub new_len: usize,
+}
+
+impl Resize {
+    /// Where the byte at `offset` is after the replacement. Offsets within the replaced bytes
+    /// are clamped to the bytes replacing them.
+    pub fn shift(&self, offset: usize) -> usize {
+        match offset {
+            o if o < self.offset => o,
+            o if o >= self.offset + self.old_len => o - self.old_len + self.new_len,
+            o => self.offset + (o - self.offset).min(self.new_len),
+        }
+    }
+
+    /// Where the bytes in `range` are after the replacement
+    pub fn shift_range(&self, range: &Range<usize>) -> Range<usize> {
+        let end = match range.end <= self.offset {
+            true => range.end,
+            false => self.shift(range.end),
+        };
+        self.shift(range.start)..end
+    }
+
+    /// Moves the sorted ranges of offsets in `ranges` along with the bytes they cover, dropping
+    /// those whose bytes were all replaced
+    pub fn shift_ranges(&self, ranges: &mut Vec<Range<usize>>) {
+        if self.old_len == self.new_len {
+            return;
+        }
+
+        for range in ranges.iter_mut() {
+            *range = self.shift_range(range);
+        }
+        ranges.retain(|r| !r.is_empty());
+    }
+}
+
+/// Pieces put in at `offset`, keeping the pieces they replaced so it can be undone
+#[derive(Clone, Debug)]
+struct Edit {
+    offset: usize,
+    old: Vec<Piece>,
+    new: Vec<Piece>,
+}
+
+impl Edit {
+    fn inverse(&self) -> Self {
+        Self {
+            offset: self.offset,
+            old: self.new.clone(),
+            new: self.old.clone(),
+        }
+    }
+
+    fn resize(&self) -> Resize {
+        Resize {
+            offset: self.offset,
+            old_len: pieces_len(&self.old),
+            new_len: pieces_len(&self.new),
+        }
+    }
+}
+
+/// Edits made to a file since it was loaded, with unlimited undo and redo. Each step that can be
+/// undone is a group of edits made together.
+#[derive(Debug)]
+pub struct EditLayer {
+    table: PieceTable,
+    undo_stack: Vec<Vec<Edit>>,
+    redo_stack: Vec<Vec<Edit>>,
+    /// Length of the undo stack when the file was last saved, or `None` if undoing and making
+    /// other edits discarded that state
+    saved_at: Option<usize>,
+    /// Sorted, non-overlapping ranges of offsets edited since the file was last saved
+    modified: Vec<Range<usize>>,
+    /// Changes in length made by the steps applied since they were last taken
+    resizes: Vec<Resize>,
+}
+
+impl Default for EditLayer {
+    fn default() -> Self {
+        Self {
+            table: PieceTable::default(),
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            saved_at: Some(0),
+            modified: Vec::new(),
+            resizes: Vec::new(),
+        }
+    }
+}
+
+impl EditLayer {
+    /// Replaces each range of `data` with the bytes given, in order, as a single step that can be
+    /// undone. Ranges are clamped to the end of `data`. Returns whether anything changed.
+    pub fn replace(&mut self, data: &mut FileData, replacements: &[(Range<usize>, &[u8])]) -> bool {
+        // Without any steps to undo or redo, nothing refers to the pieces yet
+        if self.undo_stack.is_empty() && self.redo_stack.is_empty() {
+            self.table = PieceTable::new(data.clone());
+        }
+
+        let mut edits: Vec<Edit> = Vec::new();
+        for (range, bytes) in replacements {
+            let len = self.table.len();
+            let range = range.start.min(len)..range.end.min(len);
+            if self.table.matches(range.clone(), bytes) {
+                continue;
+            }
+
+            let new = match bytes.is_empty() {
+                true => Vec::new(),
+                false => vec![Piece::new(bytes.to_vec())],
+            };
+            let old = self.table.replace(range.clone(), new.clone());
+            edits.push(Edit {
+                offset: range.start,
+                old,
+                new,
+            });
+        }
+
+        if edits.is_empty() {
+            return false;
+        }
+
+        // Undone steps can't be redone on top of other edits, so a saved state among them is lost
+        if self
+            .saved_at
+            .is_some_and(|saved_at| saved_at > self.undo_stack.len())
+        {
+            self.saved_at = None;
+        }
+        self.redo_stack.clear();
+
+        self.finish_step(data, &edits);
+        self.undo_stack.push(edits);
+        true
+    }
+
+    /// Undoes the last step, returning whether there was one
+    pub fn undo(&mut self, data: &mut FileData) -> bool {
+        let Some(edits) = self.undo_stack.pop() else {
+            return false;
+        };
+
+        let inverse: Vec<Edit> = edits.iter().rev().map(Edit::inverse).collect();
+        self.apply(data, &inverse);
+        self.redo_stack.push(edits);
+        true
+    }
+
+    /// Redoes the last step that was undone, returning whether there was one
+    pub fn redo(&mut self, data: &mut FileData) -> bool {
+        let Some(edits) = self.redo_stack.pop() else {
+            return false;
+        };
+
+        self.apply(data, &edits);
+        self.undo_stack.push(edits);
+        true
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    /// Whether the contents differ from what was last loaded or saved
+    pub fn is_dirty(&self) -> bool {
+        self.saved_at != Some(self.undo_stack.len())
+    }
+
+    /// Records that the current contents were saved, keeping the undo history
+    pub fn mark_saved(&mut self) {
+        self.saved_at = Some(self.undo_stack.len());
+        self.modified.clear();
+    }
+
+    /// Sorted, non-overlapping ranges of offsets edited since the file was last saved
+    pub fn modified(&self) -> &[Range<usize>] {
+        &self.modified
+    }
+
+    /// Takes the changes in length made since this was last called, in the order they were made
+    pub fn take_resizes(&mut self) -> Vec<Resize> {
+        std::mem::take(&mut self.resizes)
+    }
+
+    fn apply(&mut self, data: &mut FileData, edits: &[Edit]) {
+        for edit in edits {
+            self.table.replace(
+                edit.offset..edit.offset + pieces_len(&edit.old),
+                edit.new.clone(),
+            );
+        }
+        self.finish_step(data, edits);
+
+        // Undoing or redoing back to the saved contents leaves nothing to highlight
+        if !self.is_dirty() {
+            self.modified.clear();
+        }
+    }
+
+    /// Updates `data` and the edited ranges after `edits` were made to the pieces
+    fn finish_step(&mut self, data: &mut FileData, edits: &[Edit]) {
+        *data = self.table.contents();
+
+        for edit in edits {
+            let resize = edit.resize();
+            resize.shift_ranges(&mut self.modified);
+            if resize.old_len != resize.new_len {
+                self.resizes.push(resize);
+            }
+            if resize.new_len > 0 {
+                self.modified
+                    .push(edit.offset..edit.offset + resize.new_len);
+            }
+        }
+        self.modified = merge_ranges(std::mem::take(&mut self.modified));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn original() -> FileData {
+        (0..0x400).map(|i| i as u8).collect::<Vec<u8>>().into()
+    }
+
+    fn replace(table: &mut PieceTable, range: Range<usize>, bytes: &[u8]) -> Vec<Piece> {
+        table.replace(range, vec![Piece::new(bytes.to_vec())])
+    }
+
+    #[test]
+    fn replace_splits_pieces() {
+        let original = original();
+        let mut table = PieceTable::new(original.clone());
+
+        let old = replace(&mut table, 0x100..0x102, &[0xAA, 0xBB]);
+        assert_eq!(old.len(), 1);
+        assert_eq!(old[0].range, 0x100..0x102);
+
+        let ranges: Vec<Range<usize>> = table.pieces.iter().map(|p| p.range.clone()).collect();
+        assert_eq!(ranges, [0..0x100, 0..2, 0x102..0x400]);
+        assert!(FileData::ptr_eq(&table.pieces[0].data, &original));
+        assert!(FileData::ptr_eq(&table.pieces[2].data, &original));
+
+        let mut expected = original.to_vec();
+        expected[0x100..0x102].copy_from_slice(&[0xAA, 0xBB]);
+        assert_eq!(table.contents().to_vec(), expected);
+    }
+
+    #[test]
+    fn replace_copies_short_neighbours_into_one_piece() {
+        let mut table = PieceTable::new(original());
+
+        // Bytes typed one after another
+        for (i, byte) in [0x11, 0x22, 0x33].into_iter().enumerate() {
+            replace(&mut table, 0x100 + i..0x101 + i, &[byte]);
+        }
+
+        assert_eq!(table.pieces.len(), 3);
+        assert_eq!(
+            *table.pieces[1].data.read(table.pieces[1].range.clone()),
+            [0x11, 0x22, 0x33]
+        );
+    }
+
+    #[test]
+    fn replace_joins_pieces_that_continue_each_other() {
+        let original = original();
+        let mut table = PieceTable::new(original.clone());
+
+        let old = replace(&mut table, 0x100..0x200, &[0; 0x200]);
+        assert_eq!(table.pieces.len(), 3);
+
+        // Putting back the replaced piece leaves the contents in one piece again
+        table.replace(0x100..0x300, old);
+        assert_eq!(table.pieces.len(), 1);
+        assert!(FileData::ptr_eq(&table.contents(), &original));
+    }
+
+    #[test]
+    fn undo_and_redo_round_trip() {
+        let original = original();
+        let mut data = original.clone();
+        let mut edits = EditLayer::default();
+
+        let steps: [&[(Range<usize>, &[u8])]; 4] = [
+            &[(0x10..0x12, &[1, 2])],
+            &[(0x20..0x20, &[3; 0x300])],
+            &[(0x3F0..0x3F8, &[])],
+            &[(0x5..0x6, &[4]), (0x200..0x202, &[5, 6, 7])],
+        ];
+
+        let mut states = vec![data.to_vec()];
+        for step in steps {
+            assert!(edits.replace(&mut data, step));
+            states.push(data.to_vec());
+        }
+        assert!(edits.is_dirty());
+
+        let mut expected = original.to_vec();
+        expected.splice(0x10..0x12, [1, 2]);
+        expected.splice(0x20..0x20, [3; 0x300]);
+        expected.splice(0x3F0..0x3F8, []);
+        expected.splice(0x5..0x6, [4]);
+        expected.splice(0x200..0x202, [5, 6, 7]);
+        assert_eq!(data.to_vec(), expected);
+
+        for state in states.iter().rev().skip(1) {
+            assert!(edits.undo(&mut data));
+            assert_eq!(data.to_vec(), *state);
+        }
+        assert!(!edits.undo(&mut data));
+        assert!(!edits.is_dirty());
+        assert!(FileData::ptr_eq(&data, &original));
+
+        for state in states.iter().skip(1) {
+            assert!(edits.redo(&mut data));
+            assert_eq!(data.to_vec(), *state);
+        }
+        assert!(!edits.redo(&mut data));
+    }
+
+    #[test]
+    fn replacing_bytes_with_themselves_changes_nothing() {
+        let mut data = original();
+        let mut edits = EditLayer::default();
+
+        assert!(!edits.replace(&mut data, &[(0x10..0x12, &[0x10, 0x11])]));
+        assert!(!edits.can_undo());
+        assert!(!edits.is_dirty());
+    }
+}
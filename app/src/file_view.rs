@@ -1,31 +1,101 @@
 use crate::tools::data_viewer::DataViewer;
 use crate::tools::string_viewer::StringViewer;
 use crate::{
-    bin_file::{read_file_bytes, BinFile, Endianness},
-    diff_state::DiffState,
+    bin_file::{BinFile, Endianness, FileData},
+    diff_state::{changed_ranges, DeviationBasis, DiffState},
+    edit::{EditLayer, Resize},
+    patch::PatchRecord,
+    selection_ops::SelectionOp,
     settings::Settings,
     tools::symbol_tool::SymbolTool,
 };
 use anyhow::Error;
 use bdiff_hex_view::cursor_state::CursorState;
 use bdiff_hex_view::selection::HexViewSelectionState;
-use bdiff_hex_view::{HexView, HexViewOptions, HexViewState};
+use bdiff_hex_view::{
+    gap_bytes_before, ranges_contain, view_to_file_offset, Color, HexView, HexViewOptions,
+    HexViewRow, HexViewState,
+};
 use eframe::{
     egui::{self, Id},
     epaint::Color32,
 };
+use std::{
+    collections::{HashSet, VecDeque},
+    ops::Range,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+/// How long bytes changed by a reload stay highlighted
+const RECENT_CHANGE_FADE: Duration = Duration::from_secs(10);
+
+/// An earlier version of a file, kept when it's reloaded
+pub struct FileVersion {
+    pub data: FileData,
+    /// When this version was loaded
+    pub loaded: Instant,
+}
+
+/// Which earlier version of another file view a file view shows
+pub struct VersionView {
+    pub source_id: usize,
+    /// How many versions back from the current one, 1 being the previous build
+    pub versions_back: usize,
+    /// How many earlier versions the source has
+    pub available: usize,
+    /// When the version shown was loaded
+    pub loaded: Instant,
+}
+
+/// Coarse description of how long ago `instant` was, like "5 min ago"
+fn format_age(instant: Instant) -> String {
+    let age = instant.elapsed();
+    match age {
+        age if age < Duration::from_secs(60) => format!("{} s ago", age.as_secs()),
+        age if age < Duration::from_secs(60 * 60) => format!("{} min ago", age.as_secs() / 60),
+        age => format!("{} h ago", age.as_secs() / (60 * 60)),
+    }
+}
+
+/// `path` with everything but its last `limit` characters replaced by an ellipsis if it's longer
+pub fn truncated_path(path: &Path, limit: usize) -> String {
+    let path = path.to_string_lossy();
+    match path.char_indices().rev().nth(limit.saturating_sub(1)) {
+        Some((idx, _)) if idx > 0 => format!("...{}", &path[idx..]),
+        _ => path.into_owned(),
+    }
+}
 
 pub struct FileView {
     pub id: usize,
     pub file: BinFile,
     pub cur_pos: usize,
     pub pos_locked: bool,
+    /// Whether the other files are diffed against this one
+    pub reference: bool,
     pub show_selection_info: bool,
     pub show_cursor_info: bool,
     pub hv: HexView,
     sv: StringViewer,
     dv: DataViewer,
     pub st: SymbolTool,
+    /// The records of the patch this file was made with, if it was made by applying one
+    pub patch_records: Option<Vec<PatchRecord>>,
+    /// Earlier versions of the file, oldest first
+    pub history: VecDeque<FileVersion>,
+    /// When the current version was loaded
+    loaded: Instant,
+    /// Sorted ranges of file offsets that changed in the last reload
+    pub recent_changes: Vec<Range<usize>>,
+    /// Whether an earlier version of this file is shown and diffed next to it
+    pub diff_previous: bool,
+    /// Set if this view shows an earlier version of another one
+    pub version_view: Option<VersionView>,
+    /// Whether typing over the selected bytes edits the file
+    pub editable: bool,
+    /// Whether typing inserts bytes rather than overwriting them
+    pub insert_mode: bool,
     pub closed: bool,
 }
 
@@ -36,18 +106,46 @@ impl FileView {
             file,
             cur_pos: 0,
             pos_locked: false,
+            reference: false,
             show_selection_info: true,
             show_cursor_info: true,
             hv: HexView::new(id, bytes_per_row, num_rows),
             sv: StringViewer::default(),
             dv: DataViewer::default(),
             st: SymbolTool::default(),
+            patch_records: None,
+            history: VecDeque::new(),
+            loaded: Instant::now(),
+            recent_changes: Vec::new(),
+            diff_previous: false,
+            version_view: None,
+            editable: false,
+            insert_mode: false,
             closed: false,
         }
     }
 
-    pub fn reload_file(&mut self) -> Result<(), Error> {
-        self.file.data = read_file_bytes(self.file.path.clone())?;
+    /// Reloads the file, keeping up to `max_versions` earlier versions of it. The old contents of
+    /// paged files can't be kept, as they're read from the file as it is now.
+    pub fn reload_file(&mut self, max_versions: usize) -> Result<(), Error> {
+        let data = FileData::open(&self.file.path)?;
+        let old_data = std::mem::replace(&mut self.file.data, data);
+        self.file.edits = EditLayer::default();
+        self.recent_changes = match old_data.is_paged() {
+            true => Vec::new(),
+            false => changed_ranges(&old_data, &self.file.data),
+        };
+
+        if !self.recent_changes.is_empty() {
+            self.history.push_back(FileVersion {
+                data: old_data,
+                loaded: self.loaded,
+            });
+        }
+        while self.history.len() > max_versions {
+            self.history.pop_front();
+        }
+        self.loaded = Instant::now();
 
         if self.hv.selection.start() >= self.file.data.len()
             && self.hv.selection.end() >= self.file.data.len()
@@ -62,6 +160,245 @@ impl FileView {
         Ok(())
     }
 
+    /// How strongly to highlight the bytes that changed in the last reload, if the highlight
+    /// hasn't faded out yet
+    pub fn recent_change_strength(&self) -> Option<f32> {
+        let strength = 1.0 - self.loaded.elapsed().as_secs_f32() / RECENT_CHANGE_FADE.as_secs_f32();
+        (strength > 0.0 && !self.recent_changes.is_empty()).then_some(strength)
+    }
+
+    /// Names of the symbols in which bytes changed in the last reload
+    fn changed_symbols(&self) -> Vec<String> {
+        let Some(map_file) = self.st.map_file.as_ref() else {
+            return Vec::new();
+        };
+
+        let mut seen: HashSet<&str> = HashSet::new();
+        self.recent_changes
+            .iter()
+            .flat_map(|range| map_file.data.values(range.clone()))
+            .filter(|entry| seen.insert(&entry.symbol_name))
+            .map(|entry| entry.symbol_name.clone())
+            .collect()
+    }
+
+    /// Whether saving would write anything: edits, or contents that only exist in memory. The
+    /// views of earlier versions can't be saved in place.
+    pub fn can_save(&self) -> bool {
+        self.version_view.is_none() && (self.file.edits.is_dirty() || self.file.is_in_memory())
+    }
+
+    /// Name of the file, without the directories it's in
+    pub fn file_name(&self) -> String {
+        self.file
+            .path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Describes what the last reload changed, like "0x10 bytes changed in func_a and func_b"
+    pub fn reload_summary(&self) -> String {
+        const MAX_SYMBOLS: usize = 3;
+
+        let num_changed: usize = self.recent_changes.iter().map(|r| r.len()).sum();
+        let name = self.file_name();
+        if num_changed == 0 {
+            return format!("Reloaded {}, nothing changed", name);
+        }
+
+        let symbols = self.changed_symbols();
+        let symbols_text = match symbols.len() {
+            0 => String::new(),
+            1 => format!(" in {}", symbols[0]),
+            len if len <= MAX_SYMBOLS => format!(
+                " in {} and {}",
+                symbols[..len - 1].join(", "),
+                symbols[len - 1]
+            ),
+            len => format!(
+                " in {} and {} more symbols",
+                symbols[..MAX_SYMBOLS].join(", "),
+                len - MAX_SYMBOLS
+            ),
+        };
+        format!(
+            "Reloaded {}: 0x{:X} bytes changed{}",
+            name, num_changed, symbols_text
+        )
+    }
+
+    /// Name of the symbol containing the byte at global position `pos`, and the offset into it
+    pub fn symbol_at(&self, pos: usize, diff_state: &DiffState) -> Option<String> {
+        let map_file = self.st.map_file.as_ref()?;
+        let offset = view_to_file_offset(pos.checked_sub(self.cur_pos)?, diff_state.gaps(self.id))?;
+        let entry = map_file.get_entry(offset, offset + 1)?;
+
+        Some(format!(
+            "{} + 0x{:X}",
+            entry.symbol_name,
+            offset - entry.symbol_vrom
+        ))
+    }
+
+    /// The differing positions to highlight in this file's hex view and the color to use instead
+    /// of the diff color, if any
+    pub fn diff_highlight<'d>(
+        &self,
+        settings: &Settings,
+        diff_state: &'d DiffState,
+    ) -> (Option<&'d [Range<usize>]>, Option<Color>) {
+        let per_file = settings.per_file_diff_colors
+            && !(settings.deviation_basis == DeviationBasis::Reference
+                && diff_state.reference_id() == Some(self.id));
+
+        match (settings.diff_enabled, per_file) {
+            (false, _) => (None, None),
+            (true, false) => (Some(diff_state.hunks()), None),
+            (true, true) => {
+                let palette = &self.hv.style.file_diff_colors;
+                (
+                    Some(diff_state.deviations(self.id)),
+                    palette.get(self.id % palette.len().max(1)).cloned(),
+                )
+            }
+        }
+    }
+
+    /// Writes what was typed at the start of the selection, returning whether the file changed
+    pub fn type_into_selection(
+        &mut self,
+        ctx: &egui::Context,
+        gaps: Option<&[Range<usize>]>,
+    ) -> bool {
+        if !self.editable || self.version_view.is_some() {
+            return false;
+        }
+
+        let typed = self.hv.take_typed_bytes(
+            ctx,
+            &HexViewState {
+                file_data: &self.file.data,
+                file_pos: self.cur_pos,
+                global_pos: self.cur_pos,
+                diffs: None,
+                gaps,
+                ignored: None,
+                relocations: None,
+                rows: None,
+                recent_changes: None,
+                modified: None,
+            },
+            self.insert_mode,
+        );
+        let replacements: Vec<(Range<usize>, &[u8])> = typed
+            .iter()
+            .map(|t| {
+                let end = match t.insert {
+                    true => t.offset,
+                    false => t.offset + 1,
+                };
+                (t.offset..end, std::slice::from_ref(&t.value))
+            })
+            .collect();
+        self.file.replace(&replacements)
+    }
+
+    /// Deletes the selected bytes, leaving the byte after them selected. Returns whether the file
+    /// changed.
+    pub fn delete_selection(&mut self, gaps: Option<&[Range<usize>]>) -> bool {
+        let Some(range) = self
+            .hv
+            .selected_range(self.file.data.len(), self.cur_pos, gaps)
+        else {
+            return false;
+        };
+        if !self.file.delete(range.clone()) {
+            return false;
+        }
+
+        match range.start < self.file.data.len() {
+            true => {
+                let start = self.hv.selection.start().max(self.cur_pos);
+                self.hv.selection.range.first = start;
+                self.hv.selection.range.second = start;
+            }
+            false => self.hv.selection.clear(),
+        }
+        true
+    }
+
+    /// Inserts `bytes` before the selected ones and selects them. Returns whether the file
+    /// changed.
+    pub fn insert_before_selection(&mut self, bytes: &[u8], gaps: Option<&[Range<usize>]>) -> bool {
+        let Some(range) = self
+            .hv
+            .selected_range(self.file.data.len(), self.cur_pos, gaps)
+        else {
+            return false;
+        };
+        if !self.file.insert(range.start, bytes) {
+            return false;
+        }
+
+        let start = self.hv.selection.start().max(self.cur_pos);
+        self.hv.selection.range.first = start;
+        self.hv.selection.range.second = start + bytes.len() - 1;
+        true
+    }
+
+    /// Range of offsets into the file of the bytes shown at the global positions in `positions`.
+    /// If none are shown there, the range is empty and starts where they would go.
+    pub fn file_range(
+        &self,
+        positions: &Range<usize>,
+        gaps: Option<&[Range<usize>]>,
+    ) -> Range<usize> {
+        let offset = |pos: usize| {
+            let view_pos = pos.saturating_sub(self.cur_pos);
+            let gap_len = gaps.map_or(0, |gaps| gap_bytes_before(view_pos, gaps));
+            (view_pos - gap_len).min(self.file.data.len())
+        };
+        offset(positions.start)..offset(positions.end)
+    }
+
+    /// Rewrites the selected bytes with `op`. Returns whether the file changed.
+    pub fn apply_to_selection(
+        &mut self,
+        op: &SelectionOp,
+        gaps: Option<&[Range<usize>]>,
+    ) -> Result<bool, Error> {
+        let Some(range) = self
+            .hv
+            .selected_range(self.file.data.len(), self.cur_pos, gaps)
+        else {
+            return Ok(false);
+        };
+
+        let bytes = op.apply(&self.file.data.read(range.clone()), self.file.endianness)?;
+        Ok(self.file.replace(&[(range, &bytes)]))
+    }
+
+    /// Moves the file offsets kept for this file along with the bytes edits moved
+    pub fn shift_offsets(&mut self, resizes: &[Resize]) {
+        for resize in resizes {
+            resize.shift_ranges(&mut self.recent_changes);
+            for record in self.patch_records.iter_mut().flatten() {
+                record.range = resize.shift_range(&record.range);
+            }
+        }
+    }
+
+    pub fn num_offset_digits(&self) -> usize {
+        match self.file.data.len() {
+            //0..=0xFFFF => 4,
+            0x10000..=0xFFFFFFFF => 8,
+            0x100000000..=0xFFFFFFFFFFFF => 12,
+            _ => 8,
+        }
+    }
+
     pub fn show(
         &mut self,
         ctx: &egui::Context,
@@ -69,21 +406,21 @@ impl FileView {
         diff_state: &DiffState,
         can_selection_change: bool,
         global_view_pos: usize,
+        rows: Option<&[HexViewRow]>,
     ) {
         egui::Window::new(self.file.path.to_str().unwrap())
             .id(Id::new(format!("hex_view_window_{}", self.id)))
             .title_bar(false)
             .show(ctx, |ui| {
+                let gaps = diff_state.gaps(self.id);
+
                 ui.with_layout(
                     egui::Layout::left_to_right(eframe::emath::Align::Min),
                     |ui| {
-                        // Truncate file_name with leading ellipsis
-                        let name_limit = 50;
-                        let file_name = self.file.path.as_path().to_str().unwrap();
-                        let file_name_brief = if file_name.len() > name_limit {
-                            format!("...{}", &file_name[file_name.len() - name_limit..])
-                        } else {
-                            file_name.to_owned()
+                        let file_name_brief = truncated_path(&self.file.path, 50);
+                        let file_name_brief = match self.file.edits.is_dirty() {
+                            true => format!("{} *", file_name_brief),
+                            false => file_name_brief,
                         };
                         ui.label(
                             egui::RichText::new(file_name_brief)
@@ -91,7 +428,7 @@ impl FileView {
                                 .size(14.0)
                                 .color(Color32::LIGHT_GRAY),
                         )
-                        .on_hover_text(egui::RichText::new(file_name));
+                        .on_hover_text(egui::RichText::new(self.file.path.to_string_lossy()));
 
                         let (lock_text, hover_text) = match self.pos_locked {
                             true => (
@@ -109,6 +446,26 @@ impl FileView {
                             self.pos_locked = !self.pos_locked;
                         }
 
+                        let (reference_text, hover_text) = match self.reference {
+                            true => (
+                                egui::RichText::new(egui_phosphor::regular::STAR)
+                                    .color(Color32::GOLD),
+                                "Stop diffing against this file",
+                            ),
+                            false => (
+                                egui::RichText::new(egui_phosphor::regular::STAR)
+                                    .color(Color32::GRAY),
+                                "Diff other files against this one",
+                            ),
+                        };
+                        if ui
+                            .button(reference_text)
+                            .on_hover_text(hover_text)
+                            .clicked()
+                        {
+                            self.reference = !self.reference;
+                        }
+
                         match self.file.endianness {
                             Endianness::Little => {
                                 if ui
@@ -130,6 +487,34 @@ impl FileView {
                             }
                         }
 
+                        if self.version_view.is_none() {
+                            let (edit_color, hover_text) = match self.editable {
+                                true => (Color32::LIGHT_BLUE, "Stop editing"),
+                                false => (Color32::GRAY, "Edit by typing over the selected bytes"),
+                            };
+                            if ui
+                                .button(
+                                    egui::RichText::new(egui_phosphor::regular::PENCIL_SIMPLE)
+                                        .color(edit_color),
+                                )
+                                .on_hover_text(hover_text)
+                                .clicked()
+                            {
+                                self.editable = !self.editable;
+                            }
+
+                            if self.editable && self.insert_mode {
+                                ui.label(
+                                    egui::RichText::new("INS")
+                                        .monospace()
+                                        .color(Color32::LIGHT_BLUE),
+                                )
+                                .on_hover_text(
+                                    "Typing inserts bytes (press Insert to overwrite them instead)",
+                                );
+                            }
+                        }
+
                         ui.menu_button("...", |ui| {
                             ui.checkbox(&mut self.show_selection_info, "Selection info");
                             ui.checkbox(&mut self.show_cursor_info, "Cursor info");
@@ -138,6 +523,48 @@ impl FileView {
                             ui.checkbox(&mut self.st.show, "Symbols");
                         });
 
+                        match self.version_view.as_mut() {
+                            Some(version) => {
+                                ui.add(
+                                    egui::Slider::new(
+                                        &mut version.versions_back,
+                                        1..=version.available.max(1),
+                                    )
+                                    .text("builds back"),
+                                )
+                                .on_hover_text(format!("Loaded {}", format_age(version.loaded)));
+                            }
+                            None => {
+                                let history_color = match self.diff_previous {
+                                    true => Color32::LIGHT_BLUE,
+                                    false => Color32::GRAY,
+                                };
+                                let hover_text = match self.diff_previous {
+                                    true => "Stop diffing against the previous build".to_owned(),
+                                    false => format!(
+                                        "Diff against the previous build ({} kept)",
+                                        self.history.len()
+                                    ),
+                                };
+                                if ui
+                                    .add_enabled(
+                                        !self.history.is_empty(),
+                                        egui::Button::new(
+                                            egui::RichText::new(
+                                                egui_phosphor::regular::CLOCK_COUNTER_CLOCKWISE,
+                                            )
+                                            .color(history_color),
+                                        ),
+                                    )
+                                    .on_hover_text(hover_text)
+                                    .on_disabled_hover_text("No earlier versions of this file yet")
+                                    .clicked()
+                                {
+                                    self.diff_previous = !self.diff_previous;
+                                }
+                            }
+                        }
+
                         if ui.button("X").on_hover_text("Close").clicked() {
                             self.closed = true;
                         }
@@ -149,17 +576,7 @@ impl FileView {
                     |ui: &mut egui::Ui| {
                         ui.vertical(|ui| {
                             ui.group(|ui| {
-                                let diffs = match settings.diff_enabled {
-                                    true => Some(&diff_state.diffs[..]),
-                                    false => None,
-                                };
-
-                                let num_offset_digits = match self.file.data.len() {
-                                    //0..=0xFFFF => 4,
-                                    0x10000..=0xFFFFFFFF => 8,
-                                    0x100000000..=0xFFFFFFFFFFFF => 12,
-                                    _ => 8,
-                                };
+                                let (diffs, diff_color) = self.diff_highlight(settings, diff_state);
 
                                 self.hv.show(
                                     ui,
@@ -168,12 +585,25 @@ impl FileView {
                                         file_pos: self.cur_pos,
                                         global_pos: global_view_pos,
                                         diffs,
+                                        gaps,
+                                        ignored: settings
+                                            .diff_enabled
+                                            .then_some(diff_state.ignored()),
+                                        relocations: settings
+                                            .diff_enabled
+                                            .then_some(diff_state.relocations()),
+                                        rows,
+                                        recent_changes: self
+                                            .recent_change_strength()
+                                            .map(|strength| (&self.recent_changes[..], strength)),
+                                        modified: Some(self.file.edits.modified()),
                                     },
                                     CursorState::get(ctx),
                                     HexViewOptions {
                                         can_selection_change,
                                         byte_grouping: settings.byte_grouping,
-                                        num_offset_digits,
+                                        num_offset_digits: self.num_offset_digits(),
+                                        diff_color,
                                     },
                                 );
                             });
@@ -183,10 +613,19 @@ impl FileView {
                                     HexViewSelectionState::None => "No selection".to_owned(),
                                     _ => {
                                         // Convert to file coords
-                                        let start = self.hv.selection.start() as isize
-                                            - self.cur_pos as isize;
-                                        let end = self.hv.selection.end() as isize
-                                            - self.cur_pos as isize;
+                                        let to_file = |pos: usize| -> isize {
+                                            let view_pos = pos as isize - self.cur_pos as isize;
+                                            match (view_pos >= 0, gaps) {
+                                                (true, Some(gaps)) => {
+                                                    view_pos
+                                                        - gap_bytes_before(view_pos as usize, gaps)
+                                                            as isize
+                                                }
+                                                _ => view_pos,
+                                            }
+                                        };
+                                        let start = to_file(self.hv.selection.start());
+                                        let end = to_file(self.hv.selection.end() + 1) - 1;
                                         let length = end - start + 1;
 
                                         let map_entry = match self.st.map_file {
@@ -240,13 +679,27 @@ impl FileView {
                             if self.show_cursor_info {
                                 let hover_text = match self.hv.cursor_pos {
                                     Some(pos) => {
-                                        if pos < self.cur_pos {
-                                            "Not hovering".to_owned()
-                                        } else {
-                                            // Convert to file position from global position
-                                            let pos =
-                                                (pos as isize - self.cur_pos as isize) as usize;
+                                        // Show what the reference has in place of a differing byte
+                                        let reference_text = match diff_state.reference_id() {
+                                            Some(id) if id != self.id => {
+                                                match ranges_contain(diff_state.hunks(), pos) {
+                                                    true => diff_state.reference_byte(pos).map_or(
+                                                        ", reference: --".to_owned(),
+                                                        |b| format!(", reference: 0x{:02X}", b),
+                                                    ),
+                                                    false => String::new(),
+                                                }
+                                            }
+                                            _ => String::new(),
+                                        };
+
+                                        // Convert to file position from global position
+                                        let pos = match pos < self.cur_pos {
+                                            true => None,
+                                            false => view_to_file_offset(pos - self.cur_pos, gaps),
+                                        };
 
+                                        if let Some(pos) = pos {
                                             let map_entry = match self.st.map_file {
                                                 Some(ref map_file) => {
                                                     map_file.get_entry(pos, pos + 1)
@@ -257,14 +710,19 @@ impl FileView {
                                             match map_entry {
                                                 Some(entry) => {
                                                     format!(
-                                                        "Cursor: 0x{:X} ({} + 0x{})",
+                                                        "Cursor: 0x{:X} ({} + 0x{}){}",
                                                         pos,
                                                         entry.symbol_name,
-                                                        pos - entry.symbol_vrom
+                                                        pos - entry.symbol_vrom,
+                                                        reference_text
                                                     )
                                                 }
-                                                None => format!("Cursor: 0x{:X}", pos),
+                                                None => {
+                                                    format!("Cursor: 0x{:X}{}", pos, reference_text)
+                                                }
                                             }
+                                        } else {
+                                            "Not hovering".to_owned()
                                         }
                                     }
                                     None => "Not hovering".to_owned(),
@@ -274,18 +732,15 @@ impl FileView {
                         });
 
                         ui.with_layout(egui::Layout::top_down(eframe::emath::Align::Min), |ui| {
-                            self.dv.display(
-                                ui,
-                                self.id,
-                                self.hv.get_selected_bytes(&self.file.data, self.cur_pos),
-                                self.file.endianness,
-                            );
-                            self.sv.display(
-                                ui,
-                                self.id,
-                                self.hv.get_selected_bytes(&self.file.data, self.cur_pos),
-                                self.file.endianness,
-                            );
+                            let selected_bytes = self
+                                .hv
+                                .selected_range(self.file.data.len(), self.cur_pos, gaps)
+                                .map(|range| self.file.data.read(range))
+                                .unwrap_or_default();
+                            self.dv
+                                .display(ui, self.id, &selected_bytes, self.file.endianness);
+                            self.sv
+                                .display(ui, self.id, &selected_bytes, self.file.endianness);
                             self.st.display(ui);
                         });
                     },
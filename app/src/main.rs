@@ -1,18 +1,29 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
+mod alignment;
 mod app;
 mod bin_file;
+mod cli;
+mod compression;
 mod diff_state;
+mod diff_worker;
+mod edit;
 mod file_view;
+mod mips;
+mod patch;
+mod selection_ops;
 mod settings;
+mod toasts;
 mod tools;
+mod unified_view;
 mod watcher;
 mod workspace;
 
-use std::path::PathBuf;
+use std::{path::PathBuf, process::ExitCode};
 
 use app::BdiffApp;
 use argh::FromArgs;
+use cli::Command;
 use eframe::{egui::ViewportBuilder, icon_data};
 
 #[derive(FromArgs)]
@@ -21,11 +32,38 @@ struct Args {
     /// input files
     #[argh(positional)]
     files: Vec<PathBuf>,
+
+    #[argh(subcommand)]
+    command: Option<Command>,
 }
 
-fn main() {
+/// Release builds on Windows have no console of their own, so subcommands print to the one they
+/// were started from
+#[cfg(windows)]
+fn attach_parent_console() {
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+
+    // SAFETY: AttachConsole only fails, leaving output where it goes already, if there's no
+    // parent console or the process has one
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+fn main() -> ExitCode {
     let args: Args = argh::from_env();
 
+    if let Some(command) = args.command {
+        #[cfg(windows)]
+        attach_parent_console();
+        return cli::run(command);
+    }
+
     let native_options = eframe::NativeOptions {
         viewport: ViewportBuilder::default()
             .with_icon(icon_data::from_png_bytes(include_bytes!("../assets/icon.png")).unwrap()),
@@ -37,4 +75,6 @@ fn main() {
         native_options,
         Box::new(|cc| Ok(Box::new(BdiffApp::new(cc, args.files)))),
     );
+
+    ExitCode::SUCCESS
 }
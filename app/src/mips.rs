@@ -0,0 +1,50 @@
+//! Classification of differences between MIPS instruction words.
+//!
+//! When symbols move between builds, the instructions that refer to them change only in the fields
+//! the linker fills in: the target of `j`/`jal` and the immediates of `lui` (`%hi`) and of the
+//! `addiu`/load/store instructions that complete the address (`%lo`).
+
+const OPCODE_J: u32 = 0x02;
+const OPCODE_JAL: u32 = 0x03;
+const OPCODE_LUI: u32 = 0x0F;
+
+/// Instructions whose immediate can be a `%lo` relocation
+const LO_IMMEDIATE_OPCODES: [u32; 18] = [
+    0x09, // addiu
+    0x19, // daddiu
+    0x20, // lb
+    0x21, // lh
+    0x23, // lw
+    0x24, // lbu
+    0x25, // lhu
+    0x27, // lwu
+    0x28, // sb
+    0x29, // sh
+    0x2B, // sw
+    0x31, // lwc1
+    0x35, // ldc1
+    0x37, // ld
+    0x39, // swc1
+    0x3D, // sdc1
+    0x3F, // sd
+    0x0D, // ori
+];
+
+fn opcode(word: u32) -> u32 {
+    word >> 26
+}
+
+/// Whether instructions `a` and `b` differ only in a field that a relocation fills in
+pub fn is_relocation_diff(a: u32, b: u32) -> bool {
+    if a == b || opcode(a) != opcode(b) {
+        return false;
+    }
+
+    match opcode(a) {
+        // 26-bit jump target
+        OPCODE_J | OPCODE_JAL => true,
+        // Only the 16-bit immediate may differ, the registers must match
+        op if op == OPCODE_LUI || LO_IMMEDIATE_OPCODES.contains(&op) => (a >> 16) == (b >> 16),
+        _ => false,
+    }
+}
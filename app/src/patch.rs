@@ -0,0 +1,624 @@
+//! Creation and application of IPS, BPS and UPS patches.
+//!
+//! Patches are written from the ranges in which the target differs from the source, so the bytes
+//! between them can be taken from the source.
+
+use std::{fmt::Display, ops::Range, path::Path, str::FromStr};
+
+use anyhow::Error;
+
+use crate::{
+    bin_file::FileData,
+    diff_state::{DeviationBasis, DiffConfig, DiffInput, DiffMode, DiffProgress, DiffState},
+};
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+/// Offsets are 24-bit
+const IPS_MAX_OFFSET: usize = 0xFFFFFF;
+const IPS_MAX_RECORD_LEN: usize = 0xFFFF;
+
+const BPS_MAGIC: &[u8] = b"BPS1";
+const BPS_SOURCE_READ: usize = 0;
+const BPS_TARGET_READ: usize = 1;
+const BPS_SOURCE_COPY: usize = 2;
+
+const UPS_MAGIC: &[u8] = b"UPS1";
+
+/// Refuse to make anything larger than this, as the sizes come from untrusted patch headers
+const MAX_TARGET_LEN: usize = u32::MAX as usize;
+
+/// Most bytes a patch may add to the size of its source per byte of patch, so that a tiny patch
+/// can't claim a huge size. Run-length encoded IPS records come close to 0x2000.
+const MAX_GROWTH_PER_PATCH_BYTE: usize = 0x10000;
+
+/// BPS and UPS patches end with the CRC32s of the source, the target and the patch itself
+const CHECKSUMS_LEN: usize = 12;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PatchFormat {
+    Ips,
+    Bps,
+    Ups,
+}
+
+impl PatchFormat {
+    /// The format matching the extension of `path`, if any
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.to_lowercase().parse().ok()
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Ips => "ips",
+            Self::Bps => "bps",
+            Self::Ups => "ups",
+        }
+    }
+}
+
+impl FromStr for PatchFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ips" => Ok(Self::Ips),
+            "bps" => Ok(Self::Bps),
+            "ups" => Ok(Self::Ups),
+            _ => Err(format!(
+                "unknown patch format \"{}\", expected ips, bps or ups",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for PatchFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            Self::Ips => "IPS",
+            Self::Bps => "BPS",
+            Self::Ups => "UPS",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+/// Diffs `source` and `target` byte for byte from their start and returns the ranges of `target`
+/// that differ, for when the diff shown doesn't compare them that way
+pub fn positional_patch_ranges(source: &DiffInput, target: &DiffInput) -> Vec<Range<usize>> {
+    let inputs = [
+        DiffInput {
+            cur_pos: 0,
+            ..source.clone()
+        },
+        DiffInput {
+            cur_pos: 0,
+            ..target.clone()
+        },
+    ];
+    let config = DiffConfig {
+        mode: DiffMode::Positional,
+        basis: DeviationBasis::Reference,
+        ignored: Vec::new(),
+    };
+
+    DiffState::calculate(&inputs, &config, &DiffProgress::default())
+        .and_then(|diff_state| diff_state.patch_ranges(&inputs[1]))
+        .unwrap_or_default()
+}
+
+/// Makes a patch that turns `source` into `target`. `ranges` are the sorted ranges of `target`
+/// that differ from `source`, including anything past the end of `source`.
+pub fn create_patch(
+    format: PatchFormat,
+    source: &FileData,
+    target: &FileData,
+    ranges: &[Range<usize>],
+) -> Result<Vec<u8>, Error> {
+    match format {
+        PatchFormat::Ips => create_ips(source, target, ranges),
+        PatchFormat::Bps => Ok(create_bps(source, target, ranges)),
+        PatchFormat::Ups => Ok(create_ups(source, target, ranges)),
+    }
+}
+
+fn create_ips(
+    source: &FileData,
+    target: &FileData,
+    ranges: &[Range<usize>],
+) -> Result<Vec<u8>, Error> {
+    if target.len() > IPS_MAX_OFFSET + 1 {
+        return Err(Error::msg(
+            "IPS patches can't address files larger than 16 MiB, use BPS instead",
+        ));
+    }
+
+    let mut ret = IPS_MAGIC.to_vec();
+
+    for range in ranges {
+        let mut start = range.start;
+        while start < range.end {
+            // A record at this offset would read as the end of the patch, so start a byte earlier
+            if start == 0x454F46 {
+                start -= 1;
+            }
+
+            let end = range.end.min(start + IPS_MAX_RECORD_LEN);
+            ret.extend_from_slice(&start.to_be_bytes()[size_of::<usize>() - 3..]);
+            ret.extend_from_slice(&((end - start) as u16).to_be_bytes());
+            ret.extend_from_slice(&target.read(start..end));
+            start = end;
+        }
+    }
+
+    ret.extend_from_slice(IPS_EOF);
+
+    // Truncation extension
+    if target.len() < source.len() {
+        ret.extend_from_slice(&target.len().to_be_bytes()[size_of::<usize>() - 3..]);
+    }
+
+    Ok(ret)
+}
+
+/// Appends a variable-length number as BPS and UPS encode them
+fn push_number(patch: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let bits = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            patch.push(0x80 | bits);
+            break;
+        }
+        patch.push(bits);
+        value -= 1;
+    }
+}
+
+fn push_bps_action(patch: &mut Vec<u8>, action: usize, len: usize) {
+    push_number(patch, ((len - 1) << 2) | action);
+}
+
+fn create_bps(source: &FileData, target: &FileData, ranges: &[Range<usize>]) -> Vec<u8> {
+    let mut ret = BPS_MAGIC.to_vec();
+    push_number(&mut ret, source.len());
+    push_number(&mut ret, target.len());
+    // No metadata
+    push_number(&mut ret, 0);
+
+    // Bytes outside the ranges are the same in both files, so they're read from the source
+    let mut pos = 0;
+    for range in ranges {
+        if range.start > pos {
+            push_bps_action(&mut ret, BPS_SOURCE_READ, range.start - pos);
+        }
+        push_bps_action(&mut ret, BPS_TARGET_READ, range.len());
+        ret.extend_from_slice(&target.read(range.clone()));
+        pos = range.end;
+    }
+    if pos < target.len() {
+        push_bps_action(&mut ret, BPS_SOURCE_READ, target.len() - pos);
+    }
+
+    push_checksums(&mut ret, source, target);
+    ret
+}
+
+fn create_ups(source: &FileData, target: &FileData, ranges: &[Range<usize>]) -> Vec<u8> {
+    let mut ret = UPS_MAGIC.to_vec();
+    push_number(&mut ret, source.len());
+    push_number(&mut ret, target.len());
+
+    // Each record skips ahead and XORs bytes until a zero, which also uses up a byte. Bytes past
+    // the end of the source XOR with zero.
+    let mut pos = 0;
+    for range in ranges {
+        let target_bytes = target.read(range.clone());
+        let source_bytes = source.read(range.start.min(source.len())..range.end.min(source.len()));
+
+        let mut in_record = false;
+        for i in range.clone() {
+            let j = i - range.start;
+            let xor = source_bytes.get(j).copied().unwrap_or(0) ^ target_bytes[j];
+            match (xor != 0, in_record) {
+                (true, false) => {
+                    push_number(&mut ret, i - pos);
+                    ret.push(xor);
+                    in_record = true;
+                }
+                (true, true) => ret.push(xor),
+                (false, true) => {
+                    ret.push(0);
+                    pos = i + 1;
+                    in_record = false;
+                }
+                (false, false) => {}
+            }
+        }
+        if in_record {
+            ret.push(0);
+            pos = range.end + 1;
+        }
+    }
+
+    push_checksums(&mut ret, source, target);
+    ret
+}
+
+fn crc32(data: &FileData) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    data.for_each_chunk(0..data.len(), &mut |chunk| hasher.update(chunk));
+    hasher.finalize()
+}
+
+fn push_checksums(patch: &mut Vec<u8>, source: &FileData, target: &FileData) {
+    patch.extend_from_slice(&crc32(source).to_le_bytes());
+    patch.extend_from_slice(&crc32(target).to_le_bytes());
+    let patch_crc = crc32fast::hash(patch);
+    patch.extend_from_slice(&patch_crc.to_le_bytes());
+}
+
+/// A change made by a patch
+#[derive(Clone, Debug)]
+pub struct PatchRecord {
+    /// Offsets of the patched file that the record writes
+    pub range: Range<usize>,
+    pub description: String,
+}
+
+pub struct AppliedPatch {
+    pub format: PatchFormat,
+    pub data: Vec<u8>,
+    pub records: Vec<PatchRecord>,
+}
+
+struct PatchReader<'a> {
+    patch: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PatchReader<'a> {
+    fn is_done(&self) -> bool {
+        self.pos >= self.patch.len()
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.patch.len())
+            .ok_or_else(|| Error::msg("Patch ends unexpectedly"))?;
+        let ret = &self.patch[self.pos..end];
+        self.pos = end;
+        Ok(ret)
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn big_endian(&mut self, len: usize) -> Result<usize, Error> {
+        Ok(self
+            .bytes(len)?
+            .iter()
+            .fold(0, |acc, &b| (acc << 8) | b as usize))
+    }
+
+    /// Reads a variable-length number as BPS and UPS encode them
+    fn number(&mut self) -> Result<usize, Error> {
+        let invalid = || Error::msg("Invalid number in patch");
+
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let b = self.byte()?;
+            value = ((b & 0x7F) as usize)
+                .checked_mul(shift)
+                .and_then(|bits| value.checked_add(bits))
+                .ok_or_else(invalid)?;
+            if b & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_shl(7).ok_or_else(invalid)?;
+            value = value.checked_add(shift).ok_or_else(invalid)?;
+        }
+    }
+}
+
+/// Applies `patch`, whose format is detected from its header, to `source`
+pub fn apply_patch(patch: &[u8], source: &[u8]) -> Result<AppliedPatch, Error> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(patch, source)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(patch, source)
+    } else if patch.starts_with(UPS_MAGIC) {
+        apply_ups(patch, source)
+    } else {
+        Err(Error::msg("Not an IPS, BPS or UPS patch"))
+    }
+}
+
+fn apply_ips(patch: &[u8], source: &[u8]) -> Result<AppliedPatch, Error> {
+    let mut reader = PatchReader {
+        patch,
+        pos: IPS_MAGIC.len(),
+    };
+    let mut data = source.to_vec();
+    let mut records = Vec::new();
+
+    loop {
+        let header = reader.bytes(3)?;
+        if header == IPS_EOF {
+            break;
+        }
+        let offset = header.iter().fold(0, |acc, &b| (acc << 8) | b as usize);
+
+        let (bytes, description) = match reader.big_endian(2)? {
+            // Run-length encoded record
+            0 => {
+                let len = reader.big_endian(2)?;
+                let value = reader.byte()?;
+                (vec![value; len], format!("Fill with 0x{:02X}", value))
+            }
+            len => (reader.bytes(len)?.to_vec(), "Data".to_owned()),
+        };
+
+        let range = offset..offset + bytes.len();
+        if data.len() < range.end {
+            data.resize(range.end, 0);
+        }
+        data[range.clone()].copy_from_slice(&bytes);
+        records.push(PatchRecord { range, description });
+    }
+
+    // Truncation extension
+    if !reader.is_done() {
+        data.truncate(reader.big_endian(3)?);
+    }
+
+    Ok(AppliedPatch {
+        format: PatchFormat::Ips,
+        data,
+        records,
+    })
+}
+
+/// Checks the checksums at the end of a BPS or UPS patch that don't depend on the result,
+/// returning the expected CRC32 of the patched file
+fn check_source_checksums(patch: &[u8], source: &[u8]) -> Result<u32, Error> {
+    if patch.len() < CHECKSUMS_LEN {
+        return Err(Error::msg("Patch ends unexpectedly"));
+    }
+    let checksums = &patch[patch.len() - CHECKSUMS_LEN..];
+    let crc = |idx: usize| u32::from_le_bytes(checksums[idx * 4..idx * 4 + 4].try_into().unwrap());
+
+    if crc32fast::hash(&patch[..patch.len() - 4]) != crc(2) {
+        return Err(Error::msg("Patch is corrupted"));
+    }
+    if crc32fast::hash(source) != crc(0) {
+        return Err(Error::msg("Patch was made for a different file"));
+    }
+    Ok(crc(1))
+}
+
+fn checked_target_len(len: usize, source: &[u8], patch: &[u8]) -> Result<usize, Error> {
+    let max_len = patch
+        .len()
+        .saturating_mul(MAX_GROWTH_PER_PATCH_BYTE)
+        .saturating_add(source.len())
+        .min(MAX_TARGET_LEN);
+    match len <= max_len {
+        true => Ok(len),
+        false => Err(Error::msg(format!(
+            "Patched size 0x{:X} is implausibly large",
+            len
+        ))),
+    }
+}
+
+fn apply_bps(patch: &[u8], source: &[u8]) -> Result<AppliedPatch, Error> {
+    let target_crc = check_source_checksums(patch, source)?;
+    let mut reader = PatchReader {
+        patch: &patch[..patch.len() - CHECKSUMS_LEN],
+        pos: BPS_MAGIC.len(),
+    };
+
+    let _source_len = reader.number()?;
+    let target_len = checked_target_len(reader.number()?, source, patch)?;
+    let metadata_len = reader.number()?;
+    reader.bytes(metadata_len)?;
+
+    let out_of_bounds = || Error::msg("Patch reads past the end of a file");
+    let mut data: Vec<u8> = Vec::with_capacity(target_len);
+    let mut records = Vec::new();
+    let mut source_offset: usize = 0;
+    let mut target_offset: usize = 0;
+
+    // Copies are relative to where the last one of the same kind ended
+    let seek = |offset: &mut usize, reader: &mut PatchReader| -> Result<usize, Error> {
+        let value = reader.number()?;
+        let delta = value >> 1;
+        *offset = match value & 1 {
+            0 => offset.checked_add(delta),
+            _ => offset.checked_sub(delta),
+        }
+        .ok_or_else(out_of_bounds)?;
+        Ok(*offset)
+    };
+
+    while !reader.is_done() {
+        let action = reader.number()?;
+        let len = (action >> 2) + 1;
+        let start = data.len();
+        if start + len > target_len {
+            return Err(Error::msg("Patch writes past the end of the patched file"));
+        }
+
+        let description = match action & 3 {
+            BPS_SOURCE_READ => {
+                data.extend_from_slice(source.get(start..start + len).ok_or_else(out_of_bounds)?);
+                // Unchanged bytes aren't worth listing
+                continue;
+            }
+            BPS_TARGET_READ => {
+                data.extend_from_slice(reader.bytes(len)?);
+                "Data".to_owned()
+            }
+            BPS_SOURCE_COPY => {
+                let from = seek(&mut source_offset, &mut reader)?;
+                data.extend_from_slice(source.get(from..from + len).ok_or_else(out_of_bounds)?);
+                source_offset += len;
+                format!("Copy from original 0x{:X}", from)
+            }
+            // Target copy
+            _ => {
+                let from = seek(&mut target_offset, &mut reader)?;
+                // The copy may overlap the bytes it writes, so it goes byte by byte
+                for i in from..from + len {
+                    let b = *data.get(i).ok_or_else(out_of_bounds)?;
+                    data.push(b);
+                }
+                target_offset += len;
+                format!("Copy from patched 0x{:X}", from)
+            }
+        };
+
+        records.push(PatchRecord {
+            range: start..data.len(),
+            description,
+        });
+    }
+
+    if data.len() != target_len || crc32fast::hash(&data) != target_crc {
+        return Err(Error::msg(
+            "Patched file doesn't match the patch's checksum",
+        ));
+    }
+
+    Ok(AppliedPatch {
+        format: PatchFormat::Bps,
+        data,
+        records,
+    })
+}
+
+fn apply_ups(patch: &[u8], source: &[u8]) -> Result<AppliedPatch, Error> {
+    let target_crc = check_source_checksums(patch, source)?;
+    let mut reader = PatchReader {
+        patch: &patch[..patch.len() - CHECKSUMS_LEN],
+        pos: UPS_MAGIC.len(),
+    };
+
+    let _source_len = reader.number()?;
+    let target_len = checked_target_len(reader.number()?, source, patch)?;
+
+    let mut data = source.to_vec();
+    data.resize(target_len, 0);
+    let mut records = Vec::new();
+
+    let invalid = || Error::msg("Invalid number in patch");
+    let mut pos: usize = 0;
+    while !reader.is_done() {
+        pos = pos.checked_add(reader.number()?).ok_or_else(invalid)?;
+
+        let start = pos;
+        loop {
+            match reader.byte()? {
+                0 => break,
+                xor => {
+                    if let Some(b) = data.get_mut(pos) {
+                        *b ^= xor;
+                    }
+                    pos = pos.checked_add(1).ok_or_else(invalid)?;
+                }
+            }
+        }
+
+        records.push(PatchRecord {
+            range: start.min(target_len)..pos.min(target_len),
+            description: "XOR".to_owned(),
+        });
+        pos = pos.checked_add(1).ok_or_else(invalid)?;
+    }
+
+    if crc32fast::hash(&data) != target_crc {
+        return Err(Error::msg(
+            "Patched file doesn't match the patch's checksum",
+        ));
+    }
+
+    Ok(AppliedPatch {
+        format: PatchFormat::Ups,
+        data,
+        records,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bin_file::Endianness;
+
+    /// Makes a patch from `source` to `target` and checks that applying it gives back `target`
+    fn round_trip(format: PatchFormat, source: Vec<u8>, target: Vec<u8>) {
+        let input = |id: usize, data: Vec<u8>| DiffInput {
+            id,
+            cur_pos: 0,
+            data: data.into(),
+            endianness: Endianness::Big,
+        };
+        let (source, target) = (input(0, source), input(1, target));
+        let ranges = positional_patch_ranges(&source, &target);
+
+        let patch = create_patch(format, &source.data, &target.data, &ranges).unwrap();
+        let applied = apply_patch(&patch, &source.data.to_vec()).unwrap();
+        assert_eq!(applied.format, format);
+        assert_eq!(applied.data, target.data.to_vec());
+    }
+
+    #[test]
+    fn patches_round_trip() {
+        let source: Vec<u8> = (0..0x400).map(|i| (i * 13) as u8).collect();
+
+        let mut changed = source.clone();
+        changed[0] ^= 0xFF;
+        changed[0x100..0x180].fill(0);
+        changed[0x3FF] = 0x42;
+        let mut extended = changed.clone();
+        extended.extend_from_slice(&[0x55; 0x20]);
+        let truncated = changed[..0x300].to_vec();
+
+        for format in [PatchFormat::Ips, PatchFormat::Bps, PatchFormat::Ups] {
+            for target in [&changed, &extended, &truncated] {
+                round_trip(format, source.clone(), target.clone());
+            }
+        }
+    }
+
+    #[test]
+    fn ips_change_at_offset_reading_as_eof() {
+        let source = vec![0; 0x455000];
+        let mut target = source.clone();
+        target[0x454F46] = 0x01;
+
+        round_trip(PatchFormat::Ips, source, target);
+    }
+
+    #[test]
+    fn apply_rejects_size_out_of_proportion_to_patch() {
+        let source: FileData = vec![0; 0x100].into();
+        for magic in [BPS_MAGIC, UPS_MAGIC] {
+            let mut patch = magic.to_vec();
+            push_number(&mut patch, source.len());
+            push_number(&mut patch, 0xFFFF_0000);
+            if magic == BPS_MAGIC {
+                push_number(&mut patch, 0);
+            }
+            push_checksums(&mut patch, &source, &source);
+
+            let err = apply_patch(&patch, &source.to_vec()).err().unwrap();
+            assert!(err.to_string().contains("implausibly large"));
+        }
+    }
+}
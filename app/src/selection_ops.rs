@@ -0,0 +1,156 @@
+//! Operations that rewrite all of the selected bytes at once.
+
+use anyhow::Error;
+
+use crate::bin_file::Endianness;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SelectionOp {
+    /// Repeats a pattern over the bytes
+    Fill(Vec<u8>),
+    /// Adds an amount to each integer of `width` bytes, wrapping around
+    Add { width: usize, amount: u64 },
+    /// XORs the bytes with a repeated key
+    Xor(Vec<u8>),
+    /// Reverses the order of the bytes within each unit of the given size
+    ByteSwap(usize),
+    /// Reverses the order of all the bytes
+    Reverse,
+}
+
+impl SelectionOp {
+    /// Size of the units the bytes are split into, which they must make a whole number of
+    fn unit_len(&self) -> usize {
+        match self {
+            Self::Add { width, .. } | Self::ByteSwap(width) => *width,
+            _ => 1,
+        }
+    }
+
+    /// The bytes `bytes` become, reading integers with `endianness`
+    pub fn apply(&self, bytes: &[u8], endianness: Endianness) -> Result<Vec<u8>, Error> {
+        let unit_len = self.unit_len();
+        if !bytes.len().is_multiple_of(unit_len) {
+            return Err(Error::msg(format!(
+                "0x{:X} bytes are selected, which isn't a whole number of {}-byte units",
+                bytes.len(),
+                unit_len
+            )));
+        }
+
+        let mut ret = bytes.to_vec();
+        match self {
+            Self::Fill(pattern) | Self::Xor(pattern) if pattern.is_empty() => {}
+            Self::Fill(pattern) => {
+                for (byte, p) in ret.iter_mut().zip(pattern.iter().cycle()) {
+                    *byte = *p;
+                }
+            }
+            Self::Xor(key) => {
+                for (byte, k) in ret.iter_mut().zip(key.iter().cycle()) {
+                    *byte ^= k;
+                }
+            }
+            Self::Add { width, amount } => {
+                for chunk in ret.chunks_exact_mut(*width) {
+                    let value = read_int(chunk, endianness).wrapping_add(*amount);
+                    write_int(chunk, value, endianness);
+                }
+            }
+            Self::ByteSwap(width) => {
+                for chunk in ret.chunks_exact_mut(*width) {
+                    chunk.reverse();
+                }
+            }
+            Self::Reverse => ret.reverse(),
+        }
+        Ok(ret)
+    }
+}
+
+/// Reads an integer of up to 8 bytes
+fn read_int(bytes: &[u8], endianness: Endianness) -> u64 {
+    let mut buf = [0; 8];
+    match endianness {
+        Endianness::Little => {
+            buf[..bytes.len()].copy_from_slice(bytes);
+            u64::from_le_bytes(buf)
+        }
+        Endianness::Big => {
+            buf[8 - bytes.len()..].copy_from_slice(bytes);
+            u64::from_be_bytes(buf)
+        }
+    }
+}
+
+/// Writes the low bytes of `value` over an integer of up to 8 bytes
+fn write_int(bytes: &mut [u8], value: u64, endianness: Endianness) {
+    let len = bytes.len();
+    match endianness {
+        Endianness::Little => bytes.copy_from_slice(&value.to_le_bytes()[..len]),
+        Endianness::Big => bytes.copy_from_slice(&value.to_be_bytes()[8 - len..]),
+    }
+}
+
+/// Parses an amount to add, written in decimal or with a prefix like "0x"
+pub fn parse_amount(text: &str) -> Result<u64, Error> {
+    parse_int::parse(text.trim())
+        .map_err(|_| Error::msg(format!("\"{}\" isn't a whole number", text.trim())))
+}
+
+/// Parses bytes written in hex, like "DEADBEEF", "0xdead beef" or "DE AD BE EF"
+pub fn parse_hex_bytes(text: &str) -> Result<Vec<u8>, Error> {
+    let digits: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    let digits = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+        .unwrap_or(&digits);
+
+    if digits.is_empty() {
+        return Err(Error::msg("No bytes given"));
+    }
+    if !digits.len().is_multiple_of(2) || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::msg(format!(
+            "\"{}\" isn't a whole number of hex bytes",
+            text.trim()
+        )));
+    }
+
+    Ok((0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ops_reject_partial_units() {
+        let bytes = [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC];
+        let add = SelectionOp::Add {
+            width: 4,
+            amount: 1,
+        };
+        assert!(add.apply(&bytes, Endianness::Big).is_err());
+        assert!(SelectionOp::ByteSwap(4)
+            .apply(&bytes, Endianness::Big)
+            .is_err());
+
+        assert_eq!(
+            add.apply(&bytes[..4], Endianness::Big).unwrap(),
+            [0x12, 0x34, 0x56, 0x79]
+        );
+        assert_eq!(
+            SelectionOp::ByteSwap(2)
+                .apply(&bytes, Endianness::Big)
+                .unwrap(),
+            [0x34, 0x12, 0x78, 0x56, 0xBC, 0x9A]
+        );
+        // Operations on single bytes take any length
+        assert!(SelectionOp::Reverse
+            .apply(&bytes[..5], Endianness::Big)
+            .is_ok());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::diff_state::{DeviationBasis, DiffMode};
 use crate::settings::theme::ThemeSettings;
 use anyhow::{Context, Error};
 use bdiff_hex_view::byte_grouping::ByteGrouping;
@@ -14,11 +15,23 @@ pub mod ui;
 pub use theme::show_theme_settings;
 
 #[derive(Deserialize, Serialize, PartialEq, PartialOrd, Clone)]
+#[serde(default)]
 pub struct Settings {
     pub mirror_selection: bool,
     pub diff_enabled: bool,
+    pub diff_mode: DiffMode,
+    /// Color each file's differing bytes by file instead of all in the diff color
+    pub per_file_diff_colors: bool,
+    /// Fold rows that are identical in all files, keeping `context_rows` rows around differences
+    pub collapse_identical: bool,
+    pub context_rows: usize,
+    pub deviation_basis: DeviationBasis,
     pub byte_grouping: ByteGrouping,
     pub show_quick_access_bar: bool,
+    /// Show all files interleaved row by row in a single grid instead of in separate windows
+    pub unified_view: bool,
+    /// Number of earlier versions of each file kept in memory when it's reloaded
+    pub max_versions: usize,
     pub theme: ThemeSettings,
 }
 
@@ -27,8 +40,15 @@ impl Default for Settings {
         Self {
             mirror_selection: true,
             diff_enabled: true,
+            diff_mode: DiffMode::default(),
+            per_file_diff_colors: false,
+            collapse_identical: false,
+            context_rows: 3,
+            deviation_basis: DeviationBasis::default(),
             byte_grouping: ByteGrouping::default(),
             show_quick_access_bar: false,
+            unified_view: false,
+            max_versions: 8,
             theme: ThemeSettings::default(),
         }
     }
@@ -78,6 +78,27 @@ pub fn show_theme_settings(ctx: &egui::Context, settings: &mut ThemeSettings) ->
                             &mut settings.hex_view_style.selection_color,
                         );
                         color_selection(ui, "Diff color", &mut settings.hex_view_style.diff_color);
+                        color_selection(
+                            ui,
+                            "Relocation diff color",
+                            &mut settings.hex_view_style.relocation_color,
+                        );
+                        color_selection(ui, "Gap color", &mut settings.hex_view_style.gap_color);
+                        color_selection(
+                            ui,
+                            "Ignored color",
+                            &mut settings.hex_view_style.ignored_color,
+                        );
+                        color_selection(
+                            ui,
+                            "Recently changed color",
+                            &mut settings.hex_view_style.recently_changed_color,
+                        );
+                        color_selection(
+                            ui,
+                            "Modified color",
+                            &mut settings.hex_view_style.modified_color,
+                        );
                         color_selection(
                             ui,
                             "Null color",
@@ -90,6 +111,19 @@ pub fn show_theme_settings(ctx: &egui::Context, settings: &mut ThemeSettings) ->
                         );
                     });
 
+                egui::CollapsingHeader::new("Per-file Diff Colors")
+                    .default_open(false)
+                    .show(ui, |ui| {
+                        for (i, color) in settings
+                            .hex_view_style
+                            .file_diff_colors
+                            .iter_mut()
+                            .enumerate()
+                        {
+                            color_selection(ui, &format!("File {} color", i + 1), color);
+                        }
+                    });
+
                 egui::CollapsingHeader::new("Ascii Area Colors")
                     .default_open(true)
                     .show(ui, |ui| {
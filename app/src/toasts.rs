@@ -0,0 +1,49 @@
+use std::time::{Duration, Instant};
+
+use eframe::egui::{self, Align2, Id};
+
+/// How long a toast stays on screen
+const TOAST_DURATION: Duration = Duration::from_secs(6);
+
+struct Toast {
+    text: String,
+    shown: Instant,
+}
+
+/// Short notices shown in the bottom right corner for a few seconds
+#[derive(Default)]
+pub struct Toasts {
+    toasts: Vec<Toast>,
+}
+
+impl Toasts {
+    pub fn add(&mut self, text: impl Into<String>) {
+        self.toasts.push(Toast {
+            text: text.into(),
+            shown: Instant::now(),
+        });
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context) {
+        self.toasts
+            .retain(|toast| toast.shown.elapsed() < TOAST_DURATION);
+        if self.toasts.is_empty() {
+            return;
+        }
+
+        egui::Area::new(Id::new("toasts"))
+            .anchor(Align2::RIGHT_BOTTOM, [-8.0, -8.0])
+            .order(egui::Order::Foreground)
+            .interactable(false)
+            .show(ctx, |ui| {
+                for toast in self.toasts.iter() {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.label(&toast.text);
+                    });
+                }
+            });
+
+        // Keep repainting so toasts disappear on time
+        ctx.request_repaint_after(Duration::from_millis(250));
+    }
+}
@@ -0,0 +1,372 @@
+use std::{collections::HashMap, ops::Range, sync::Arc};
+
+use eframe::egui::{self, RichText};
+use iset::IntervalMap;
+
+use crate::{
+    diff_state::{DiffProgress, DiffState},
+    diff_worker::{DiffTask, DiffTaskStatus},
+    file_view::FileView,
+    tools::map_file::MapFileEntry,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+enum SortColumn {
+    Name,
+    Size,
+    #[default]
+    Mismatched,
+    Match,
+}
+
+struct SymbolMismatch {
+    name: String,
+    size: usize,
+    mismatched: usize,
+}
+
+impl SymbolMismatch {
+    fn match_fraction(&self) -> f32 {
+        match self.size {
+            0 => 1.0,
+            size => 1.0 - self.mismatched.min(size) as f32 / size as f32,
+        }
+    }
+}
+
+/// Maps increasing positions relative to a file's position to offsets into the file
+struct GapCursor<'a> {
+    gaps: &'a [Range<usize>],
+    /// Index of the first gap that doesn't end before the last position mapped
+    idx: usize,
+    /// Number of bytes in the gaps before it
+    skipped: usize,
+}
+
+impl<'a> GapCursor<'a> {
+    fn new(gaps: &'a [Range<usize>]) -> Self {
+        Self {
+            gaps,
+            idx: 0,
+            skipped: 0,
+        }
+    }
+
+    /// Offset of the first file byte at or after `view_pos`, which mustn't be less than the last
+    fn offset(&mut self, view_pos: usize) -> usize {
+        while let Some(gap) = self.gaps.get(self.idx).filter(|g| g.end <= view_pos) {
+            self.skipped += gap.len();
+            self.idx += 1;
+        }
+        let partial = self
+            .gaps
+            .get(self.idx)
+            .map_or(0, |g| view_pos.saturating_sub(g.start));
+        view_pos - self.skipped - partial
+    }
+}
+
+/// The symbols of the file whose map file the mismatches are counted by
+struct SymbolSource {
+    /// Name of the file
+    name: String,
+    symbols: Arc<IntervalMap<usize, MapFileEntry>>,
+    cur_pos: usize,
+    gaps: Vec<Range<usize>>,
+}
+
+/// What the statistics are calculated from, taken from the diff so they can be calculated on a
+/// worker thread
+struct StatsInput {
+    hunks: Vec<Range<usize>>,
+    /// Global positions spanned by the files
+    span: Range<usize>,
+    ignored: Vec<Range<usize>>,
+    symbol_source: Option<SymbolSource>,
+}
+
+impl StatsInput {
+    fn new(diff_state: &DiffState, file_views: &[FileView]) -> Self {
+        let span = file_views.iter().map(|fv| fv.cur_pos).min().unwrap_or(0)
+            ..file_views
+                .iter()
+                .map(|fv| fv.cur_pos + diff_state.display_len(fv))
+                .max()
+                .unwrap_or(0);
+
+        // Symbols come from the reference's map if it has one
+        let reference_id = diff_state.reference_id();
+        let symbol_source = file_views
+            .iter()
+            .filter(|fv| Some(fv.id) == reference_id)
+            .chain(file_views.iter())
+            .find_map(|fv| {
+                Some(SymbolSource {
+                    name: fv.file.path.display().to_string(),
+                    symbols: fv.st.map_file.as_ref()?.data.clone(),
+                    cur_pos: fv.cur_pos,
+                    gaps: diff_state.gaps(fv.id).map_or(Vec::new(), <[_]>::to_vec),
+                })
+            });
+
+        Self {
+            hunks: diff_state.hunks().to_vec(),
+            span,
+            ignored: diff_state.ignored().to_vec(),
+            symbol_source,
+        }
+    }
+}
+
+struct DiffStatistics {
+    diff_bytes: usize,
+    num_hunks: usize,
+    compared_len: usize,
+    /// Name of the file whose map the symbols come from
+    map_source: Option<String>,
+    symbols: Vec<SymbolMismatch>,
+}
+
+impl DiffStatistics {
+    /// Returns `None` if the calculation was cancelled
+    fn calculate(input: StatsInput, progress: &DiffProgress) -> Option<Self> {
+        let ignored_len: usize = input
+            .ignored
+            .iter()
+            .map(|r| {
+                r.end
+                    .min(input.span.end)
+                    .saturating_sub(r.start.max(input.span.start))
+            })
+            .sum();
+
+        let mut symbols: HashMap<&str, SymbolMismatch> = HashMap::new();
+        if let Some(source) = &input.symbol_source {
+            progress.add_total(input.hunks.len());
+            let mut gap_cursor = GapCursor::new(&source.gaps);
+
+            for hunk in input.hunks.iter() {
+                if progress.is_cancelled() {
+                    return None;
+                }
+                progress.advance(1);
+
+                // Bytes before the file or in its gaps aren't in any of its symbols
+                let start = gap_cursor.offset(hunk.start.saturating_sub(source.cur_pos));
+                let end = gap_cursor.offset(hunk.end.saturating_sub(source.cur_pos));
+                if start >= end {
+                    continue;
+                }
+
+                // Each byte counts for the first symbol containing it
+                let mut counted_to = start;
+                for (range, entry) in source.symbols.iter(start..end) {
+                    let overlap = range.start.max(counted_to)..range.end.min(end);
+                    if overlap.is_empty() {
+                        continue;
+                    }
+                    counted_to = overlap.end;
+
+                    symbols
+                        .entry(&entry.symbol_name)
+                        .or_insert_with(|| SymbolMismatch {
+                            name: entry.symbol_name.clone(),
+                            size: entry.symbol_size,
+                            mismatched: 0,
+                        })
+                        .mismatched += overlap.len();
+                }
+            }
+        }
+
+        Some(Self {
+            diff_bytes: input.hunks.iter().map(|h| h.len()).sum(),
+            num_hunks: input.hunks.len(),
+            // Ignored bytes aren't compared
+            compared_len: input.span.len() - ignored_len,
+            map_source: input.symbol_source.as_ref().map(|s| s.name.clone()),
+            symbols: symbols.into_values().collect(),
+        })
+    }
+
+    fn sort(&mut self, column: SortColumn, ascending: bool) {
+        self.symbols.sort_by(|a, b| {
+            let ordering = match column {
+                SortColumn::Name => a.name.cmp(&b.name),
+                SortColumn::Size => a.size.cmp(&b.size),
+                SortColumn::Mismatched => a.mismatched.cmp(&b.mismatched),
+                SortColumn::Match => a.match_fraction().total_cmp(&b.match_fraction()),
+            };
+            match ascending {
+                true => ordering,
+                false => ordering.reverse(),
+            }
+        });
+    }
+}
+
+#[derive(Default)]
+pub struct DiffStats {
+    pub show: bool,
+    sort_column: SortColumn,
+    ascending: bool,
+    stats: Option<DiffStatistics>,
+    task: Option<DiffTask<DiffStatistics>>,
+}
+
+impl DiffStats {
+    /// Drops the statistics so they are recalculated the next time they are shown
+    pub fn invalidate(&mut self) {
+        self.stats = None;
+        self.task = None;
+    }
+
+    pub fn display(
+        &mut self,
+        ctx: &egui::Context,
+        diff_state: &DiffState,
+        file_views: &[FileView],
+    ) {
+        if !self.show {
+            return;
+        }
+
+        if self.stats.is_none() && self.task.is_none() {
+            let input = StatsInput::new(diff_state, file_views);
+            self.task = Some(DiffTask::spawn(move |progress| {
+                DiffStatistics::calculate(input, progress)
+            }));
+        }
+        if let Some(task) = &self.task {
+            match task.poll() {
+                DiffTaskStatus::Running => ctx.request_repaint(),
+                DiffTaskStatus::Finished(stats) => {
+                    let mut stats = *stats;
+                    stats.sort(self.sort_column, self.ascending);
+                    self.stats = Some(stats);
+                    self.task = None;
+                }
+                DiffTaskStatus::Aborted => self.task = None,
+            }
+        }
+
+        let mut open = self.show;
+        let mut refresh = false;
+
+        egui::Window::new("Diff statistics")
+            .open(&mut open)
+            .resizable(true)
+            .show(ctx, |ui| {
+                let Some(stats) = self.stats.as_mut() else {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label("Calculating statistics");
+                    });
+                    return;
+                };
+
+                let match_percent = match stats.compared_len {
+                    0 => 100.0,
+                    len => 100.0 * (1.0 - stats.diff_bytes as f64 / len as f64),
+                };
+
+                egui::Grid::new("diff_stats_summary").show(ui, |ui| {
+                    ui.label("Differing bytes");
+                    ui.label(RichText::new(format!("0x{:X}", stats.diff_bytes)).monospace());
+                    ui.end_row();
+
+                    ui.label("Hunks");
+                    ui.label(RichText::new(stats.num_hunks.to_string()).monospace());
+                    ui.end_row();
+
+                    ui.label("Match");
+                    ui.label(RichText::new(format!("{:.3}%", match_percent)).monospace());
+                    ui.end_row();
+                });
+
+                if ui.button("Refresh").clicked() {
+                    refresh = true;
+                }
+
+                ui.separator();
+
+                let Some(map_source) = &stats.map_source else {
+                    ui.label("Load a map file to see mismatches per symbol");
+                    return;
+                };
+                ui.label(format!(
+                    "{} mismatched symbols in {}",
+                    stats.symbols.len(),
+                    map_source
+                ));
+
+                let mut sort_clicked = None;
+                egui::ScrollArea::vertical()
+                    .auto_shrink(false)
+                    .show(ui, |ui| {
+                        egui::Grid::new("diff_stats_symbols")
+                            .striped(true)
+                            .show(ui, |ui| {
+                                for (column, text) in [
+                                    (SortColumn::Name, "Symbol"),
+                                    (SortColumn::Size, "Size"),
+                                    (SortColumn::Mismatched, "Mismatched"),
+                                    (SortColumn::Match, "Match"),
+                                ] {
+                                    let text = match (self.sort_column == column, self.ascending) {
+                                        (true, true) => {
+                                            format!("{} {}", text, egui_phosphor::regular::CARET_UP)
+                                        }
+                                        (true, false) => {
+                                            format!(
+                                                "{} {}",
+                                                text,
+                                                egui_phosphor::regular::CARET_DOWN
+                                            )
+                                        }
+                                        (false, _) => text.to_owned(),
+                                    };
+                                    if ui.button(text).clicked() {
+                                        sort_clicked = Some(column);
+                                    }
+                                }
+                                ui.end_row();
+
+                                for symbol in stats.symbols.iter() {
+                                    ui.label(RichText::new(&symbol.name).monospace());
+                                    ui.label(
+                                        RichText::new(format!("0x{:X}", symbol.size)).monospace(),
+                                    );
+                                    ui.label(
+                                        RichText::new(format!("0x{:X}", symbol.mismatched))
+                                            .monospace(),
+                                    );
+                                    ui.label(
+                                        RichText::new(format!(
+                                            "{:.1}%",
+                                            100.0 * symbol.match_fraction()
+                                        ))
+                                        .monospace(),
+                                    );
+                                    ui.end_row();
+                                }
+                            });
+                    });
+
+                if let Some(column) = sort_clicked {
+                    // Clicking the sorted column again flips the order
+                    self.ascending = match self.sort_column == column {
+                        true => !self.ascending,
+                        false => column == SortColumn::Name,
+                    };
+                    self.sort_column = column;
+                    stats.sort(self.sort_column, self.ascending);
+                }
+            });
+
+        self.show = open;
+        if refresh {
+            self.invalidate();
+        }
+    }
+}
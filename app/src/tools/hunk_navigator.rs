@@ -0,0 +1,128 @@
+use std::ops::Range;
+
+use eframe::egui::{self, RichText};
+
+use crate::{diff_state::DiffState, file_view::FileView};
+
+/// What was done with a hunk in the list
+pub enum HunkAction {
+    /// Jump to the hunk
+    Jump(Range<usize>),
+    /// Copy the bytes file view `from` has in the hunk over those of file view `to`
+    Copy {
+        hunk: Range<usize>,
+        from: usize,
+        to: usize,
+    },
+}
+
+#[derive(Default)]
+pub struct HunkNavigator {
+    pub show: bool,
+    dock_left: bool,
+}
+
+impl HunkNavigator {
+    /// Shows the list of hunks, returning what was done with one of them. Hunks can only be
+    /// copied with `can_copy`, as their positions are out of date while a diff is running or
+    /// after one was cancelled.
+    pub fn display(
+        &mut self,
+        ctx: &egui::Context,
+        diff_state: &DiffState,
+        file_views: &[FileView],
+        view: Range<usize>,
+        can_copy: bool,
+    ) -> Option<HunkAction> {
+        if !self.show {
+            return None;
+        }
+
+        let mut action = None;
+
+        let panel = match self.dock_left {
+            true => egui::SidePanel::left("hunk_navigator_left"),
+            false => egui::SidePanel::right("hunk_navigator_right"),
+        };
+
+        panel.resizable(true).show(ctx, |ui| {
+            ui.with_layout(
+                egui::Layout::left_to_right(eframe::emath::Align::Min),
+                |ui| {
+                    ui.add(egui::Label::new(RichText::new("Hunks").monospace()));
+
+                    let (dock_icon, dock_text) = match self.dock_left {
+                        true => (egui_phosphor::regular::ARROW_LINE_RIGHT, "Dock right"),
+                        false => (egui_phosphor::regular::ARROW_LINE_LEFT, "Dock left"),
+                    };
+                    if ui.button(dock_icon).on_hover_text(dock_text).clicked() {
+                        self.dock_left = !self.dock_left;
+                    }
+
+                    if ui.button("X").on_hover_text("Close").clicked() {
+                        self.show = false;
+                    }
+                },
+            );
+
+            let hunks = diff_state.hunks();
+            ui.label(format!("{} hunks", hunks.len()));
+            ui.separator();
+
+            let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+            egui::ScrollArea::vertical().auto_shrink(false).show_rows(
+                ui,
+                row_height,
+                hunks.len(),
+                |ui, rows| {
+                    for hunk in hunks[rows].iter() {
+                        // Locate hunks within the reference file when there is one
+                        let offset = diff_state
+                            .reference_offset(hunk.start)
+                            .unwrap_or(hunk.start);
+                        let location = format!("0x{:08X} (len 0x{:X})", offset, hunk.len());
+                        let reference_id = diff_state.reference_id();
+                        let text = match file_views
+                            .iter()
+                            .filter(|fv| Some(fv.id) == reference_id)
+                            .chain(file_views.iter())
+                            .find_map(|fv| fv.symbol_at(hunk.start, diff_state))
+                        {
+                            Some(symbol) => format!("{} {}", location, symbol),
+                            None => location,
+                        };
+
+                        let in_view = hunk.start < view.end && hunk.end > view.start;
+                        let response =
+                            ui.selectable_label(in_view, RichText::new(text).monospace());
+                        if response.clicked() {
+                            action = Some(HunkAction::Jump(hunk.clone()));
+                        }
+                        response.context_menu(|ui| {
+                            // Earlier versions of a file can be copied from, but not edited
+                            for to in file_views.iter().filter(|fv| fv.version_view.is_none()) {
+                                for from in file_views.iter().filter(|fv| fv.id != to.id) {
+                                    let text = format!(
+                                        "Copy from {} to {}",
+                                        from.file_name(),
+                                        to.file_name()
+                                    );
+                                    if ui.add_enabled(can_copy, egui::Button::new(text)).clicked() {
+                                        action = Some(HunkAction::Copy {
+                                            hunk: hunk.clone(),
+                                            from: from.id,
+                                            to: to.id,
+                                        });
+                                        ui.close_menu();
+                                    }
+                                }
+                            }
+                        });
+                    }
+                },
+            );
+        });
+
+        action
+    }
+}
@@ -29,14 +29,15 @@ pub struct MapFileEntry {
 #[derive(Default)]
 pub struct MapFile {
     pub path: PathBuf,
-    pub data: IntervalMap<usize, MapFileEntry>,
+    /// Shared so the symbols can be read on worker threads
+    pub data: Arc<IntervalMap<usize, MapFileEntry>>,
     watcher: Option<notify::RecommendedWatcher>,
     pub modified: Arc<AtomicBool>,
 }
 
 impl MapFile {
     pub fn from_path(path: PathBuf) -> Result<Self, Error> {
-        let data = collect_data(path.clone());
+        let data = Arc::new(collect_data(path.clone()));
 
         let mut ret = Self {
             path: path.clone(),
@@ -56,7 +57,7 @@ impl MapFile {
     }
 
     pub fn reload(&mut self) -> Result<(), Error> {
-        self.data = collect_data(self.path.clone());
+        self.data = Arc::new(collect_data(self.path.clone()));
 
         Ok(())
     }
@@ -74,6 +75,10 @@ impl MapFile {
             None => None,
         }
     }
+
+    pub fn find_symbol(&self, name: &str) -> Option<&MapFileEntry> {
+        self.data.values(..).find(|entry| entry.symbol_name == name)
+    }
 }
 
 fn collect_data(path: PathBuf) -> IntervalMap<usize, MapFileEntry> {
@@ -0,0 +1,199 @@
+use std::ops::Range;
+
+use bdiff_hex_view::{file_to_view_offset, view_to_file_offset};
+use eframe::{
+    egui::{self, Color32, Rect, Sense, Stroke},
+    epaint::pos2,
+};
+
+use crate::{diff_state::DiffState, file_view::FileView};
+
+const MINIMAP_WIDTH: f32 = 28.0;
+
+/// Diff density of each bucket, with the length and number of buckets it was calculated for
+struct DensityCache {
+    total_len: usize,
+    num_buckets: usize,
+    density: Vec<f32>,
+}
+
+pub struct Minimap {
+    pub show: bool,
+    /// Draw the symbol and segment boundaries of the reference's map file
+    pub show_boundaries: bool,
+    density: Option<DensityCache>,
+}
+
+impl Default for Minimap {
+    fn default() -> Minimap {
+        Minimap {
+            show: true,
+            show_boundaries: false,
+            density: None,
+        }
+    }
+}
+
+/// Fraction of the bytes of each bucket that lie within `hunks`
+fn diff_density(hunks: &[Range<usize>], total_len: usize, num_buckets: usize) -> Vec<f32> {
+    let mut density = vec![0.0; num_buckets];
+    let bucket_len = (total_len as f32 / num_buckets as f32).max(1.0);
+
+    for hunk in hunks {
+        let first = (hunk.start as f32 / bucket_len) as usize;
+        let last = ((hunk.end - 1) as f32 / bucket_len) as usize;
+
+        for (bucket, density) in density.iter_mut().enumerate().take(last + 1).skip(first) {
+            let bucket_start = (bucket as f32 * bucket_len) as usize;
+            let bucket_end = ((bucket + 1) as f32 * bucket_len) as usize;
+            let overlap = hunk
+                .end
+                .min(bucket_end)
+                .saturating_sub(hunk.start.max(bucket_start));
+            *density += overlap as f32 / bucket_len;
+        }
+    }
+
+    density
+}
+
+impl Minimap {
+    /// Drops the diff density so it's recalculated the next time it's shown, as the hunks changed
+    pub fn invalidate(&mut self) {
+        self.density = None;
+    }
+
+    fn density(&mut self, hunks: &[Range<usize>], total_len: usize, num_buckets: usize) -> &[f32] {
+        let cache = match self.density.take() {
+            Some(cache) if cache.total_len == total_len && cache.num_buckets == num_buckets => {
+                cache
+            }
+            _ => DensityCache {
+                total_len,
+                num_buckets,
+                density: diff_density(hunks, total_len, num_buckets),
+            },
+        };
+        &self.density.insert(cache).density
+    }
+
+    /// Shows an overview of the whole address range next to the file views, shaded by how much
+    /// differs. Returns the position that was clicked or dragged to.
+    pub fn display(
+        &mut self,
+        ctx: &egui::Context,
+        diff_state: &DiffState,
+        file_views: &[FileView],
+        total_len: usize,
+        view: Range<usize>,
+    ) -> Option<usize> {
+        if !self.show || file_views.is_empty() || total_len == 0 {
+            return None;
+        }
+
+        let mut clicked = None;
+
+        egui::SidePanel::right("minimap")
+            .resizable(false)
+            .exact_width(MINIMAP_WIDTH)
+            .show(ctx, |ui| {
+                let (rect, res) =
+                    ui.allocate_exact_size(ui.available_size(), Sense::click_and_drag());
+                let painter = ui.painter_at(rect);
+                let visuals = ui.visuals();
+
+                let to_y = |pos: usize| {
+                    rect.top() + rect.height() * (pos as f32 / total_len as f32).min(1.0)
+                };
+                let to_pos = |y: f32| {
+                    (((y - rect.top()) / rect.height()).clamp(0.0, 1.0) * total_len as f32) as usize
+                };
+
+                painter.rect_filled(rect, 0.0, visuals.extreme_bg_color);
+
+                let diff_color: Color32 = file_views[0].hv.style.diff_color.clone().into();
+                let num_buckets = rect.height().max(1.0) as usize;
+                for (bucket, &density) in self
+                    .density(diff_state.hunks(), total_len, num_buckets)
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, density)| **density > 0.0)
+                {
+                    // Even a single differing byte should be visible
+                    let alpha = 0.3 + 0.7 * density.min(1.0);
+                    let y = rect.top() + bucket as f32;
+                    painter.rect_filled(
+                        Rect::from_min_max(pos2(rect.left(), y), pos2(rect.right(), y + 1.0)),
+                        0.0,
+                        diff_color.gamma_multiply(alpha),
+                    );
+                }
+
+                // Symbol boundaries are faint, segment boundaries stand out
+                let reference_id = diff_state.reference_id();
+                let map_view = file_views
+                    .iter()
+                    .filter(|fv| Some(fv.id) == reference_id)
+                    .chain(file_views.iter())
+                    .find(|fv| fv.st.map_file.is_some());
+                let to_global = |fv: &FileView, offset: usize| {
+                    fv.cur_pos + file_to_view_offset(offset, diff_state.gaps(fv.id))
+                };
+                if let Some(fv) = map_view.filter(|_| self.show_boundaries) {
+                    let map_file = fv.st.map_file.as_ref().unwrap();
+                    let mut last_segment = None;
+                    for (_, entry) in map_file.data.iter(..) {
+                        let y = to_y(to_global(fv, entry.symbol_vrom));
+                        painter.hline(
+                            rect.left()..=rect.left() + rect.width() / 3.0,
+                            y,
+                            Stroke::new(1.0, visuals.weak_text_color().gamma_multiply(0.5)),
+                        );
+
+                        if last_segment != Some(entry.seg_vrom) {
+                            last_segment = Some(entry.seg_vrom);
+                            let y = to_y(to_global(fv, entry.seg_vrom as usize));
+                            painter.hline(
+                                rect.x_range(),
+                                y,
+                                Stroke::new(1.0, visuals.strong_text_color()),
+                            );
+                        }
+                    }
+                }
+
+                // Current viewport
+                let view_rect = Rect::from_min_max(
+                    pos2(rect.left(), to_y(view.start)),
+                    pos2(rect.right(), to_y(view.end).max(to_y(view.start) + 2.0)),
+                );
+                painter.rect_stroke(
+                    view_rect,
+                    0.0,
+                    Stroke::new(1.0, visuals.selection.stroke.color),
+                );
+
+                if let Some(pointer) = res.interact_pointer_pos() {
+                    clicked = Some(to_pos(pointer.y));
+                }
+
+                if let Some(hover) = res.hover_pos() {
+                    let pos = to_pos(hover.y);
+                    let symbol = map_view.and_then(|fv| {
+                        let offset = view_to_file_offset(
+                            pos.checked_sub(fv.cur_pos)?,
+                            diff_state.gaps(fv.id),
+                        )?;
+                        let entry = fv.st.map_file.as_ref()?.get_entry(offset, offset + 1)?;
+                        Some(entry.symbol_name.clone())
+                    });
+                    res.on_hover_text(match symbol {
+                        Some(symbol) => format!("0x{:X} {}", pos, symbol),
+                        None => format!("0x{:X}", pos),
+                    });
+                }
+            });
+
+        clicked
+    }
+}
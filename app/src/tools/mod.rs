@@ -1,5 +1,9 @@
 pub mod data_viewer;
+pub mod diff_stats;
+pub mod hunk_navigator;
+pub mod minimap;
+pub mod patch_records;
 pub mod string_viewer;
 pub(crate) mod symbol_tool;
 
-mod map_file;
+pub(crate) mod map_file;
@@ -0,0 +1,111 @@
+use std::ops::Range;
+
+use bdiff_hex_view::file_to_view_offset;
+use eframe::egui::{self, RichText};
+
+use crate::{diff_state::DiffState, file_view::FileView, patch::PatchRecord};
+
+enum PatchRecordRow<'a> {
+    File(&'a FileView),
+    Record(&'a FileView, &'a PatchRecord),
+}
+
+#[derive(Default)]
+pub struct PatchRecords {
+    pub show: bool,
+}
+
+impl PatchRecords {
+    /// Shows the records of the patches that files were made with, returning the global position
+    /// of the record that was clicked
+    pub fn display(
+        &mut self,
+        ctx: &egui::Context,
+        diff_state: &DiffState,
+        file_views: &[FileView],
+        view: Range<usize>,
+    ) -> Option<usize> {
+        if !self.show {
+            return None;
+        }
+
+        let mut clicked = None;
+
+        egui::SidePanel::left("patch_records")
+            .resizable(true)
+            .show(ctx, |ui| {
+                ui.with_layout(
+                    egui::Layout::left_to_right(eframe::emath::Align::Min),
+                    |ui| {
+                        ui.add(egui::Label::new(RichText::new("Patch records").monospace()));
+
+                        if ui.button("X").on_hover_text("Close").clicked() {
+                            self.show = false;
+                        }
+                    },
+                );
+                ui.separator();
+
+                let rows: Vec<PatchRecordRow> = file_views
+                    .iter()
+                    .filter_map(|fv| Some((fv, fv.patch_records.as_ref()?)))
+                    .flat_map(|(fv, records)| {
+                        std::iter::once(PatchRecordRow::File(fv)).chain(
+                            records
+                                .iter()
+                                .map(move |record| PatchRecordRow::Record(fv, record)),
+                        )
+                    })
+                    .collect();
+
+                if rows.is_empty() {
+                    ui.label("Apply a patch from the File menu to list its records");
+                    return;
+                }
+
+                let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+                egui::ScrollArea::vertical().auto_shrink(false).show_rows(
+                    ui,
+                    row_height,
+                    rows.len(),
+                    |ui, range| {
+                        for row in rows[range].iter() {
+                            match row {
+                                PatchRecordRow::File(fv) => {
+                                    ui.label(
+                                        RichText::new(fv.file.path.display().to_string())
+                                            .monospace()
+                                            .strong(),
+                                    );
+                                }
+                                PatchRecordRow::Record(fv, record) => {
+                                    let pos = |offset: usize| {
+                                        fv.cur_pos
+                                            + file_to_view_offset(offset, diff_state.gaps(fv.id))
+                                    };
+                                    let start = pos(record.range.start);
+                                    let end = start + record.range.len();
+
+                                    let text = format!(
+                                        "0x{:08X} (len 0x{:X}) {}",
+                                        record.range.start,
+                                        record.range.len(),
+                                        record.description
+                                    );
+                                    let in_view = start < view.end && end > view.start;
+                                    if ui
+                                        .selectable_label(in_view, RichText::new(text).monospace())
+                                        .clicked()
+                                    {
+                                        clicked = Some(start);
+                                    }
+                                }
+                            }
+                        }
+                    },
+                );
+            });
+
+        clicked
+    }
+}
@@ -79,14 +79,10 @@ impl SymbolTool {
 
     pub fn load_file(&mut self, path: &std::path::Path) {
         match path.extension() {
-            Some(ext) => {
-                if ext == "map" {
-                    self.load_map_file(path);
-                } else {
-                    self.load_elf_file(path);
-                }
+            Some(ext) if ext == "map" => {
+                self.load_map_file(path);
             }
-            None => {
+            _ => {
                 self.load_elf_file(path);
             }
         }
@@ -0,0 +1,159 @@
+use bdiff_hex_view::{
+    cursor_state::CursorState, view_to_file_offset, HexViewOptions, HexViewRow, HexViewState,
+};
+use eframe::{
+    egui::{self, RichText},
+    epaint::Color32,
+};
+
+use crate::{
+    diff_state::DiffState,
+    file_view::{truncated_path, FileView},
+    settings::Settings,
+};
+
+/// Label of a file in the unified view, with the name truncated with a leading ellipsis and
+/// marked if it has unsaved edits
+fn file_label(idx: usize, fv: &FileView) -> String {
+    let label = format!("{}: {}", idx + 1, truncated_path(&fv.file.path, 40));
+    match fv.file.edits.is_dirty() {
+        true => format!("{} *", label),
+        false => label,
+    }
+}
+
+/// Shows all files in a single grid, each row once per file directly under each other.
+/// `can_selection_change` tells whether a file view may change its selection.
+#[allow(clippy::too_many_arguments)]
+pub fn show_unified_view(
+    ctx: &egui::Context,
+    file_views: &mut [FileView],
+    settings: &Settings,
+    diff_state: &DiffState,
+    global_view_pos: usize,
+    rows: Option<&[HexViewRow]>,
+    num_rows: usize,
+    can_selection_change: impl Fn(usize) -> bool,
+) {
+    let Some(first) = file_views.first() else {
+        return;
+    };
+
+    let bytes_per_row = first.hv.bytes_per_row;
+    let default_rows: Vec<HexViewRow>;
+    let rows = match rows {
+        Some(rows) => rows,
+        None => {
+            default_rows = (0..num_rows)
+                .map(|r| HexViewRow::Bytes(global_view_pos + r * bytes_per_row))
+                .collect();
+            &default_rows
+        }
+    };
+
+    let cursor_state = CursorState::get(ctx);
+    for fv in file_views.iter_mut() {
+        fv.hv.cursor_pos = None;
+    }
+
+    egui::CentralPanel::default().show(ctx, |ui| {
+        for (idx, fv) in file_views.iter().enumerate() {
+            let color = match fv.reference {
+                true => Color32::GOLD,
+                false => Color32::LIGHT_GRAY,
+            };
+            ui.label(RichText::new(file_label(idx, fv)).monospace().color(color));
+        }
+        ui.separator();
+
+        ui.scope(|ui| {
+            ui.spacing_mut().item_spacing.y = 0.0;
+
+            // Runs of byte rows are laid out in grids, with folded rows in between them
+            let mut grid_idx = 0;
+            let mut r = 0;
+            while r < rows.len() {
+                if let HexViewRow::Folded(range) = &rows[r] {
+                    file_views[0].hv.show_folded_row(ui, range);
+                    r += 1;
+                    continue;
+                }
+
+                let run_end = rows[r..]
+                    .iter()
+                    .position(|row| matches!(row, HexViewRow::Folded(_)))
+                    .map_or(rows.len(), |len| r + len);
+
+                egui::Grid::new(format!("unified_grid_{}", grid_idx))
+                    .spacing([0.0, 0.0])
+                    .min_col_width(0.0)
+                    .show(ui, |ui| {
+                        for row in rows[r..run_end].iter() {
+                            let HexViewRow::Bytes(row_start) = *row else {
+                                continue;
+                            };
+
+                            for (idx, fv) in file_views.iter_mut().enumerate() {
+                                ui.label(
+                                    RichText::new(format!("{} ", idx + 1))
+                                        .monospace()
+                                        .color(Color32::DARK_GRAY),
+                                );
+
+                                let (diffs, diff_color) = fv.diff_highlight(settings, diff_state);
+                                let options = HexViewOptions {
+                                    can_selection_change: can_selection_change(fv.id),
+                                    byte_grouping: settings.byte_grouping,
+                                    num_offset_digits: fv.num_offset_digits(),
+                                    diff_color,
+                                };
+                                let state = HexViewState {
+                                    file_data: &fv.file.data,
+                                    file_pos: fv.cur_pos,
+                                    global_pos: global_view_pos,
+                                    diffs,
+                                    gaps: diff_state.gaps(fv.id),
+                                    ignored: settings.diff_enabled.then_some(diff_state.ignored()),
+                                    relocations: settings
+                                        .diff_enabled
+                                        .then_some(diff_state.relocations()),
+                                    rows: None,
+                                    recent_changes: fv
+                                        .recent_change_strength()
+                                        .map(|strength| (&fv.recent_changes[..], strength)),
+                                    modified: Some(fv.file.edits.modified()),
+                                };
+                                fv.hv
+                                    .show_row(ui, &state, row_start, cursor_state, &options);
+                                ui.end_row();
+                            }
+
+                            // Leave some room between rows
+                            ui.add_space(4.0);
+                            ui.end_row();
+                        }
+                    });
+
+                grid_idx += 1;
+                r = run_end;
+            }
+        });
+
+        ui.separator();
+
+        let hover_text = file_views
+            .iter()
+            .enumerate()
+            .find_map(|(idx, fv)| {
+                let pos = fv.hv.cursor_pos?;
+                let offset =
+                    view_to_file_offset(pos.checked_sub(fv.cur_pos)?, diff_state.gaps(fv.id))?;
+                Some(match fv.symbol_at(pos, diff_state) {
+                    Some(symbol) => format!("Cursor: {}: 0x{:X} ({})", idx + 1, offset, symbol),
+                    None => format!("Cursor: {}: 0x{:X}", idx + 1, offset),
+                })
+            })
+            .unwrap_or_else(|| "Not hovering".to_owned());
+        ui.label(RichText::new(hover_text).monospace());
+    });
+}
@@ -11,24 +11,45 @@ use serde::{Deserialize, Serialize};
 pub struct WorkspaceFile {
     pub path: PathBuf,
     pub map: Option<PathBuf>,
+    /// Whether the other files are diffed against this one
+    #[serde(default)]
+    pub reference: bool,
 }
 
 impl From<PathBuf> for WorkspaceFile {
     fn from(path: PathBuf) -> Self {
-        Self { path, map: None }
+        Self {
+            path,
+            map: None,
+            reference: false,
+        }
     }
 }
 
 impl From<&Path> for WorkspaceFile {
     fn from(path: &Path) -> Self {
         let path: PathBuf = path.into();
-        Self { path, map: None }
+        Self {
+            path,
+            map: None,
+            reference: false,
+        }
     }
 }
 
+/// A region of the reference file that is left out of diffs
+#[derive(Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum IgnoreRegion {
+    Range { offset: usize, length: usize },
+    Symbol { symbol: String },
+}
+
 #[derive(Clone, Deserialize, Serialize, Default)]
 pub struct Workspace {
     pub files: Vec<WorkspaceFile>,
+    #[serde(default)]
+    pub ignore: Vec<IgnoreRegion>,
 }
 
 pub fn read_workspace_json(config_path: &Path) -> Result<Workspace, Error> {
@@ -4,6 +4,7 @@ use crate::byte_grouping::ByteGrouping;
 use crate::cursor_state::CursorState;
 use crate::selection::{HexViewSelection, HexViewSelectionSide, HexViewSelectionState};
 use egui::{self, Color32, FontId, Sense, Separator};
+use std::ops::Range;
 
 #[derive(Clone, Default, PartialEq)]
 pub struct HexView {
@@ -13,19 +14,119 @@ pub struct HexView {
     pub num_rows: usize,
     pub selection: HexViewSelection,
     pub cursor_pos: Option<usize>,
+    /// The high nibble typed for a byte, which isn't written until its low nibble is typed
+    pending_nibble: Option<PendingNibble>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct PendingNibble {
+    /// Global position of the byte
+    pos: usize,
+    high: u8,
 }
 
 pub struct HexViewOptions {
     pub can_selection_change: bool,
     pub byte_grouping: ByteGrouping,
     pub num_offset_digits: usize,
+    /// Color for differing bytes in place of the style's diff color
+    pub diff_color: Option<Color>,
+}
+
+/// The bytes of a file shown in a hex view, which don't have to be in memory all at once
+pub trait ByteSource {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The byte at `offset`, if there is one
+    fn get(&self, offset: usize) -> Option<u8>;
+}
+
+impl ByteSource for [u8] {
+    fn len(&self) -> usize {
+        <[u8]>::len(self)
+    }
+
+    fn get(&self, offset: usize) -> Option<u8> {
+        <[u8]>::get(self, offset).copied()
+    }
 }
 
 pub struct HexViewState<'state> {
-    pub file_data: &'state [u8],
+    pub file_data: &'state dyn ByteSource,
     pub file_pos: usize,
     pub global_pos: usize,
-    pub diffs: Option<&'state [bool]>,
+    /// Sorted, non-overlapping ranges of global positions that differ from other files
+    pub diffs: Option<&'state [Range<usize>]>,
+    /// Sorted ranges, relative to `file_pos`, in which the view shows a gap instead of file bytes
+    pub gaps: Option<&'state [Range<usize>]>,
+    /// Sorted, non-overlapping ranges of global positions that are left out of diffs
+    pub ignored: Option<&'state [Range<usize>]>,
+    /// Sorted, non-overlapping ranges of global positions that differ only in relocated fields
+    pub relocations: Option<&'state [Range<usize>]>,
+    /// Rows to show in place of `num_rows` consecutive rows starting at `global_pos`
+    pub rows: Option<&'state [HexViewRow]>,
+    /// Sorted, non-overlapping ranges of file offsets that changed in the last reload, with how
+    /// strongly to highlight them, fading from 1 to 0
+    pub recent_changes: Option<(&'state [Range<usize>], f32)>,
+    /// Sorted, non-overlapping ranges of file offsets edited since the file was last saved
+    pub modified: Option<&'state [Range<usize>]>,
+}
+
+/// A byte typed into a hex view
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TypedByte {
+    /// Offset into the file, taking the bytes typed before it into account
+    pub offset: usize,
+    pub value: u8,
+    /// Whether the byte goes in before `offset` rather than over the byte there
+    pub insert: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HexViewRow {
+    /// A row of bytes starting at a global position
+    Bytes(usize),
+    /// A range of global positions folded into a single row
+    Folded(Range<usize>),
+}
+
+/// Whether `pos` lies within one of the sorted, non-overlapping `ranges`
+pub fn ranges_contain(ranges: &[Range<usize>], pos: usize) -> bool {
+    let idx = ranges.partition_point(|r| r.end <= pos);
+    ranges.get(idx).is_some_and(|r| r.start <= pos)
+}
+
+/// Number of gap bytes that come before `view_pos` (a position relative to the file's position)
+pub fn gap_bytes_before(view_pos: usize, gaps: &[Range<usize>]) -> usize {
+    gaps.iter()
+        .take_while(|g| g.start < view_pos)
+        .map(|g| g.end.min(view_pos) - g.start)
+        .sum()
+}
+
+/// Maps an offset in the file to a position relative to the file's position
+pub fn file_to_view_offset(offset: usize, gaps: Option<&[Range<usize>]>) -> usize {
+    gaps.unwrap_or_default()
+        .iter()
+        .fold(offset, |view_pos, gap| match gap.start <= view_pos {
+            true => view_pos + gap.len(),
+            false => view_pos,
+        })
+}
+
+/// Maps a position relative to the file's position to an offset in the file, or `None` for gaps
+pub fn view_to_file_offset(view_pos: usize, gaps: Option<&[Range<usize>]>) -> Option<usize> {
+    match gaps {
+        Some(gaps) => match ranges_contain(gaps, view_pos) {
+            true => None,
+            false => Some(view_pos - gap_bytes_before(view_pos, gaps)),
+        },
+        None => Some(view_pos),
+    }
 }
 
 impl HexView {
@@ -37,6 +138,7 @@ impl HexView {
             num_rows,
             selection: HexViewSelection::default(),
             cursor_pos: None,
+            pending_nibble: None,
         }
     }
 
@@ -44,19 +146,30 @@ impl HexView {
         self.style = style;
     }
 
-    pub fn get_selected_bytes<'data>(&self, data: &'data [u8], file_pos: usize) -> &'data [u8] {
-        match self.selection.state {
-            HexViewSelectionState::None => &[],
-            HexViewSelectionState::Selecting | HexViewSelectionState::Selected => {
-                let start = (self.selection.start() as isize - file_pos as isize).max(0) as usize;
-                let end = (self.selection.end() as isize - file_pos as isize).max(0) as usize;
-                if start < data.len() {
-                    &data[start..(end + 1).min(data.len())]
-                } else {
-                    &[]
-                }
-            }
+    /// Range of offsets into a file `len` bytes long that is selected, if any of it is
+    pub fn selected_range(
+        &self,
+        len: usize,
+        file_pos: usize,
+        gaps: Option<&[Range<usize>]>,
+    ) -> Option<Range<usize>> {
+        if self.selection.state == HexViewSelectionState::None {
+            return None;
         }
+
+        let start = self.selection.start().saturating_sub(file_pos);
+        let end = (self.selection.end() + 1).saturating_sub(file_pos);
+
+        // Gap bytes within the selection don't map to anything in the file
+        let (start, end) = match gaps {
+            Some(gaps) => (
+                start - gap_bytes_before(start, gaps),
+                end - gap_bytes_before(end, gaps),
+            ),
+            None => (start, end),
+        };
+
+        (start < len && start < end).then(|| start..end.min(len))
     }
 
     fn show_offset(&mut self, num_digits: usize, current_pos: isize, ui: &mut egui::Ui) {
@@ -98,26 +211,45 @@ impl HexView {
         }
     }
 
-    fn get_selection_color(&self, pos: usize) -> Color {
+    /// Background color of the byte at global position `pos`
+    fn get_background_color(&self, state: &HexViewState, pos: usize) -> Color {
         if self.selection.contains(pos) {
-            self.style.selection_color.clone()
-        } else {
-            Color32::TRANSPARENT.into()
+            return self.style.selection_color.clone();
+        }
+
+        let Some(offset) = self.file_offset(state, pos) else {
+            return Color32::TRANSPARENT.into();
+        };
+
+        if state
+            .modified
+            .is_some_and(|modified| ranges_contain(modified, offset))
+        {
+            return self.style.modified_color.clone();
+        }
+
+        let recently_changed = state
+            .recent_changes
+            .and_then(|(changes, strength)| ranges_contain(changes, offset).then_some(strength));
+        match recently_changed {
+            Some(strength) => Color32::from(self.style.recently_changed_color.clone())
+                .gamma_multiply(strength)
+                .into(),
+            None => Color32::TRANSPARENT.into(),
         }
     }
 
     fn show_hex(
         &mut self,
         ui: &mut egui::Ui,
-        row: usize,
+        row_start: usize,
         row_data: &[Option<u8>],
         state: &HexViewState,
         cursor_state: CursorState,
         options: &HexViewOptions,
     ) {
         let mut i = 0;
-        let mut cur_pos = state.file_pos + row * self.bytes_per_row;
-        let mut global_pos = state.global_pos + row * self.bytes_per_row;
+        let mut global_pos = row_start;
 
         while i < self.bytes_per_row {
             let byte_grouping: usize = options.byte_grouping.into();
@@ -127,29 +259,43 @@ impl HexView {
             }
 
             let byte: Option<u8> = row_data[i];
-
-            let byte_text = match byte {
-                Some(byte) => format!("{:02X}", byte),
-                None => "  ".to_string(),
+            let in_gap = self.is_gap(state, global_pos);
+
+            let pending = self.pending_nibble.filter(|p| p.pos == global_pos);
+            let byte_text = match (byte, pending) {
+                (Some(_), Some(pending)) => format!("{:X}_", pending.high),
+                (Some(byte), None) => format!("{:02X}", byte),
+                (None, _) if in_gap => "--".to_string(),
+                (None, _) => "  ".to_string(),
             };
 
             let hex_label = egui::Label::new(
                 egui::RichText::new(byte_text)
                     .font(FontId::monospace(self.style.font_size))
-                    .color(
-                        if state
-                            .diffs
-                            .is_some_and(|diffs| global_pos < diffs.len() && diffs[global_pos])
-                        {
-                            self.style.diff_color.clone()
-                        } else {
-                            match byte {
-                                Some(0) => self.style.hex_null_color.clone(),
-                                _ => self.style.other_hex_color.clone(),
-                            }
-                        },
-                    )
-                    .background_color(self.get_selection_color(global_pos)),
+                    .color(if in_gap {
+                        self.style.gap_color.clone()
+                    } else if byte.is_some() && self.is_ignored(state, global_pos) {
+                        self.style.ignored_color.clone()
+                    } else if state
+                        .diffs
+                        .is_some_and(|diffs| ranges_contain(diffs, global_pos))
+                    {
+                        options
+                            .diff_color
+                            .clone()
+                            .unwrap_or_else(|| self.style.diff_color.clone())
+                    } else if state
+                        .relocations
+                        .is_some_and(|relocations| ranges_contain(relocations, global_pos))
+                    {
+                        self.style.relocation_color.clone()
+                    } else {
+                        match byte {
+                            Some(0) => self.style.hex_null_color.clone(),
+                            _ => self.style.other_hex_color.clone(),
+                        }
+                    })
+                    .background_color(self.get_background_color(state, global_pos)),
             )
             .sense(Sense::click_and_drag());
 
@@ -157,7 +303,7 @@ impl HexView {
 
             if byte.is_some() {
                 if res.contains_pointer() {
-                    self.cursor_pos = Some(cur_pos);
+                    self.cursor_pos = Some(global_pos);
                 }
                 if options.can_selection_change {
                     self.handle_selection(
@@ -170,7 +316,6 @@ impl HexView {
                 }
             }
             i += 1;
-            cur_pos += 1;
             global_pos += 1;
 
             if i < self.bytes_per_row {
@@ -182,22 +327,24 @@ impl HexView {
     fn show_ascii(
         &mut self,
         ui: &mut egui::Ui,
-        row: usize,
+        row_start: usize,
         row_data: &[Option<u8>],
         state: &HexViewState,
         cursor_state: CursorState,
         options: &HexViewOptions,
     ) {
         let mut i = 0;
-        let mut cur_pos = state.file_pos + row * self.bytes_per_row;
-        let mut global_pos = state.global_pos + row * self.bytes_per_row;
+        let mut global_pos = row_start;
 
         while i < self.bytes_per_row {
             let byte: Option<u8> = row_data[i];
 
+            let in_gap = self.is_gap(state, global_pos);
+
             let ascii_char = match byte {
                 Some(32..=126) => byte.unwrap() as char,
                 Some(_) => '·',
+                None if in_gap => '-',
                 None => ' ',
             };
 
@@ -205,11 +352,15 @@ impl HexView {
                 egui::RichText::new(ascii_char)
                     .font(FontId::monospace(self.style.font_size))
                     .color(match byte {
+                        Some(_) if self.is_ignored(state, global_pos) => {
+                            self.style.ignored_color.clone()
+                        }
                         Some(0) => self.style.ascii_null_color.clone(),
                         Some(32..=126) => self.style.ascii_color.clone(),
+                        None if in_gap => self.style.gap_color.clone(),
                         _ => self.style.other_ascii_color.clone(),
                     })
-                    .background_color(self.get_selection_color(global_pos)),
+                    .background_color(self.get_background_color(state, global_pos)),
             )
             .sense(Sense::click_and_drag());
 
@@ -218,7 +369,7 @@ impl HexView {
 
             if byte.is_some() {
                 if res.contains_pointer() {
-                    self.cursor_pos = Some(cur_pos);
+                    self.cursor_pos = Some(global_pos);
                 }
                 if options.can_selection_change {
                     self.handle_selection(
@@ -231,34 +382,112 @@ impl HexView {
                 }
             }
             i += 1;
-            cur_pos += 1;
             global_pos += 1;
         }
     }
 
-    fn get_display_bytes(
-        &self,
-        data: &[u8],
-        file_offset: usize,
-        global_offset: usize,
-    ) -> Vec<Option<u8>> {
-        let num_bytes = self.bytes_per_row * self.num_rows;
-        let pos: isize = global_offset as isize - file_offset as isize;
-
-        if pos > 0 && (pos as usize) > data.len() {
-            vec![None; num_bytes]
-        } else {
-            let mut bytes = Vec::with_capacity(num_bytes);
-            for i in 0..num_bytes {
-                let idx = pos + i as isize;
-                if idx >= 0 && (idx as usize) < data.len() {
-                    bytes.push(Some(data[idx as usize]));
-                } else {
-                    bytes.push(None);
+    /// Offset into the file of the byte at global position `pos`, if the view shows one there
+    fn file_offset(&self, state: &HexViewState, pos: usize) -> Option<usize> {
+        let offset = view_to_file_offset(pos.checked_sub(state.file_pos)?, state.gaps)?;
+        (offset < state.file_data.len()).then_some(offset)
+    }
+
+    /// Turns text typed while bytes are selected into bytes written at the start of the
+    /// selection: pairs of hex digits in the hex column, holding on to the first digit of a pair
+    /// until the second is typed, and printable characters in the ASCII column. With `insert`,
+    /// each new byte goes in before the selected one instead of over it. The selection moves on
+    /// to the next byte after each byte that's completed. Returns the bytes in the order they
+    /// were typed.
+    pub fn take_typed_bytes(
+        &mut self,
+        ctx: &egui::Context,
+        state: &HexViewState,
+        insert: bool,
+    ) -> Vec<TypedByte> {
+        if self.selection.state == HexViewSelectionState::None {
+            return Vec::new();
+        }
+
+        let typed: Vec<char> = ctx.input_mut(|i| {
+            let mut typed = Vec::new();
+            i.events.retain(|event| match event {
+                egui::Event::Text(text) => {
+                    typed.extend(text.chars());
+                    false
+                }
+                _ => true,
+            });
+            typed
+        });
+
+        let mut ret: Vec<TypedByte> = Vec::new();
+        for c in typed {
+            let pos = self.selection.start();
+            let Some(offset) = self.file_offset(state, pos) else {
+                break;
+            };
+
+            let value = match self.selection.side {
+                HexViewSelectionSide::Hex => {
+                    let Some(digit) = c.to_digit(16).map(|d| d as u8) else {
+                        continue;
+                    };
+                    match self.pending_nibble.take().filter(|p| p.pos == pos) {
+                        Some(pending) => pending.high << 4 | digit,
+                        None => {
+                            self.pending_nibble = Some(PendingNibble { pos, high: digit });
+                            continue;
+                        }
+                    }
                 }
+                HexViewSelectionSide::Ascii => match c {
+                    ' '..='~' => c as u8,
+                    _ => continue,
+                },
+            };
+            ret.push(TypedByte {
+                offset,
+                value,
+                insert,
+            });
+            self.pending_nibble = None;
+
+            // Inserted bytes push the selected one along, so there's always a next byte
+            if insert || self.file_offset(state, pos + 1).is_some() {
+                self.selection.range.first = pos + 1;
+                self.selection.range.second = pos + 1;
+                self.selection.state = HexViewSelectionState::Selected;
             }
-            bytes
         }
+        ret
+    }
+
+    fn is_gap(&self, state: &HexViewState, global_pos: usize) -> bool {
+        global_pos >= state.file_pos
+            && view_to_file_offset(global_pos - state.file_pos, state.gaps).is_none()
+    }
+
+    fn is_ignored(&self, state: &HexViewState, global_pos: usize) -> bool {
+        state
+            .ignored
+            .is_some_and(|ignored| ranges_contain(ignored, global_pos))
+    }
+
+    /// The bytes shown in the row starting at global position `row_start`
+    fn get_display_bytes(&self, state: &HexViewState, row_start: usize) -> Vec<Option<u8>> {
+        let data = state.file_data;
+        let pos: isize = row_start as isize - state.file_pos as isize;
+
+        let mut bytes = Vec::with_capacity(self.bytes_per_row);
+        for i in 0..self.bytes_per_row {
+            let view_pos = pos + i as isize;
+            let idx = match view_pos >= 0 {
+                true => view_to_file_offset(view_pos as usize, state.gaps),
+                false => None,
+            };
+            bytes.push(idx.and_then(|idx| data.get(idx)));
+        }
+        bytes
     }
 
     pub fn show(
@@ -268,42 +497,60 @@ impl HexView {
         cursor_state: CursorState,
         options: HexViewOptions,
     ) {
-        let data = self.get_display_bytes(state.file_data, state.file_pos, state.global_pos);
-
-        let grid_rect = egui::Grid::new(format!("hex_grid{}", self.id))
-            .striped(true)
-            .spacing([0.0, 0.0])
-            .min_col_width(0.0)
-            .num_columns(40)
-            .show(ui, |ui| {
-                let mut current_pos = state.global_pos as isize - state.file_pos as isize;
-                let mut row_chunks = data.chunks(self.bytes_per_row);
-
-                let mut r = 0;
-                while r < self.num_rows {
-                    let row_data = row_chunks.next().unwrap_or_default();
-
-                    self.show_offset(options.num_offset_digits, current_pos, ui);
-
-                    ui.add(Spacer::default().spacing_x(8.0));
-                    ui.add(Separator::default().vertical().spacing(0.0));
-                    ui.add(Spacer::default().spacing_x(8.0));
-
-                    self.show_hex(ui, r, row_data, state, cursor_state, &options);
+        let default_rows: Vec<HexViewRow>;
+        let rows = match state.rows {
+            Some(rows) => rows,
+            None => {
+                default_rows = (0..self.num_rows)
+                    .map(|r| HexViewRow::Bytes(state.global_pos + r * self.bytes_per_row))
+                    .collect();
+                &default_rows
+            }
+        };
 
-                    ui.add(Spacer::default().spacing_x(8.0));
-                    ui.add(Separator::default().vertical().spacing(0.0));
-                    ui.add(Spacer::default().spacing_x(8.0));
+        let mut grid_rect = egui::Rect::NOTHING;
 
-                    self.show_ascii(ui, r, row_data, state, cursor_state, &options);
+        ui.scope(|ui| {
+            ui.spacing_mut().item_spacing.y = 0.0;
 
-                    current_pos += self.bytes_per_row as isize;
+            // Runs of byte rows are laid out in grids, with folded rows in between them
+            let mut grid_idx = 0;
+            let mut r = 0;
+            while r < rows.len() {
+                if let HexViewRow::Folded(range) = &rows[r] {
+                    let res = self.show_folded_row(ui, range);
+                    grid_rect = grid_rect.union(res.rect);
                     r += 1;
-                    ui.end_row();
+                    continue;
                 }
-            })
-            .response
-            .rect;
+
+                let run_end = rows[r..]
+                    .iter()
+                    .position(|row| matches!(row, HexViewRow::Folded(_)))
+                    .map_or(rows.len(), |len| r + len);
+
+                let rect = egui::Grid::new(format!("hex_grid{}_{}", self.id, grid_idx))
+                    .striped(true)
+                    .spacing([0.0, 0.0])
+                    .min_col_width(0.0)
+                    .num_columns(40)
+                    .show(ui, |ui| {
+                        for row in rows[r..run_end].iter() {
+                            let HexViewRow::Bytes(row_start) = *row else {
+                                continue;
+                            };
+                            self.show_row(ui, state, row_start, cursor_state, &options);
+                            ui.end_row();
+                        }
+                    })
+                    .response
+                    .rect;
+                grid_rect = grid_rect.union(rect);
+
+                grid_idx += 1;
+                r = run_end;
+            }
+        });
 
         if let Some(cursor_pos) = ui.input(|i| i.pointer.hover_pos()) {
             if !grid_rect.contains(cursor_pos) {
@@ -312,6 +559,48 @@ impl HexView {
         }
     }
 
+    /// Shows a row standing in for the folded global positions in `range`
+    pub fn show_folded_row(&self, ui: &mut egui::Ui, range: &Range<usize>) -> egui::Response {
+        ui.label(
+            egui::RichText::new(format!("… 0x{:X} identical bytes …", range.len()))
+                .font(FontId::monospace(self.style.font_size))
+                .color(self.style.offset_leading_zero_color.clone()),
+        )
+    }
+
+    /// Adds the offset, hex and ascii cells of the row starting at global position `row_start` to
+    /// the grid being laid out in `ui`, without ending the grid row
+    pub fn show_row(
+        &mut self,
+        ui: &mut egui::Ui,
+        state: &HexViewState,
+        row_start: usize,
+        cursor_state: CursorState,
+        options: &HexViewOptions,
+    ) {
+        let row_data = self.get_display_bytes(state, row_start);
+        let view_pos = row_start as isize - state.file_pos as isize;
+
+        // The offset column shows file offsets, which skip over gaps
+        let current_pos = match (view_pos >= 0, state.gaps) {
+            (true, Some(gaps)) => view_pos - gap_bytes_before(view_pos as usize, gaps) as isize,
+            _ => view_pos,
+        };
+        self.show_offset(options.num_offset_digits, current_pos, ui);
+
+        ui.add(Spacer::default().spacing_x(8.0));
+        ui.add(Separator::default().vertical().spacing(0.0));
+        ui.add(Spacer::default().spacing_x(8.0));
+
+        self.show_hex(ui, row_start, &row_data, state, cursor_state, options);
+
+        ui.add(Spacer::default().spacing_x(8.0));
+        ui.add(Separator::default().vertical().spacing(0.0));
+        ui.add(Spacer::default().spacing_x(8.0));
+
+        self.show_ascii(ui, row_start, &row_data, state, cursor_state, options);
+    }
+
     pub fn handle_selection(
         &mut self,
         ui: &mut egui::Ui,
@@ -323,6 +612,7 @@ impl HexView {
         if res.hovered() {
             if cursor_state == CursorState::Pressed {
                 self.selection.begin(pos, side);
+                self.pending_nibble = None;
             }
 
             self.cursor_pos = Some(pos);
@@ -331,15 +621,15 @@ impl HexView {
         if let Some(cursor_pos) = ui.input(|i| i.pointer.hover_pos()) {
             if res.rect.contains(cursor_pos) {
                 match cursor_state {
-                    CursorState::StillDown => {
-                        if self.selection.state == HexViewSelectionState::Selecting {
-                            self.selection.update(pos);
-                        }
+                    CursorState::StillDown
+                        if self.selection.state == HexViewSelectionState::Selecting =>
+                    {
+                        self.selection.update(pos);
                     }
-                    CursorState::Released => {
-                        if self.selection.state == HexViewSelectionState::Selecting {
-                            self.selection.finalize(pos);
-                        }
+                    CursorState::Released
+                        if self.selection.state == HexViewSelectionState::Selecting =>
+                    {
+                        self.selection.finalize(pos);
                     }
                     _ => {}
                 }
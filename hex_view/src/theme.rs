@@ -2,6 +2,7 @@ use egui::Color32;
 use serde::{Deserialize, Serialize};
 
 #[derive(Deserialize, Serialize, PartialEq, PartialOrd, Clone)]
+#[serde(default)]
 pub struct HexViewStyle {
     // Offset colors
     pub offset_text_color: Color,
@@ -10,6 +11,17 @@ pub struct HexViewStyle {
     // Hex View colors
     pub selection_color: Color,
     pub diff_color: Color,
+    /// Colors for the bytes in which each file deviates from the others, picked by file
+    pub file_diff_colors: Vec<Color>,
+    pub gap_color: Color,
+    /// Color for bytes that differ only in relocated fields
+    pub relocation_color: Color,
+    /// Color for bytes that are left out of diffs
+    pub ignored_color: Color,
+    /// Background color for bytes that changed in the last reload, fading out over time
+    pub recently_changed_color: Color,
+    /// Background color for bytes edited since the file was last saved
+    pub modified_color: Color,
     pub hex_null_color: Color,
     pub other_hex_color: Color,
 
@@ -29,6 +41,19 @@ impl Default for HexViewStyle {
 
             selection_color: Color32::DARK_GREEN.into(),
             diff_color: Color32::RED.into(),
+            file_diff_colors: vec![
+                Color32::RED.into(),
+                Color32::from_rgb(255, 150, 0).into(),
+                Color32::YELLOW.into(),
+                Color32::LIGHT_BLUE.into(),
+                Color32::from_rgb(230, 100, 230).into(),
+                Color32::LIGHT_GREEN.into(),
+            ],
+            gap_color: Color32::from_gray(60).into(),
+            relocation_color: Color32::from_rgb(200, 150, 255).into(),
+            ignored_color: Color32::from_rgb(70, 80, 100).into(),
+            recently_changed_color: Color32::from_rgb(120, 90, 0).into(),
+            modified_color: Color32::from_rgb(30, 60, 130).into(),
             hex_null_color: Color32::DARK_GRAY.into(),
             other_hex_color: Color32::GRAY.into(),
 